@@ -1,4 +1,4 @@
-use clap::{arg, Parser};
+use clap::Parser;
 use ssh_test_server::{SshServerBuilder, User};
 use tokio::signal;
 use tracing::info;
@@ -17,6 +17,10 @@ struct Args {
     #[arg(long)]
     port: Option<u16>,
 
+    /// Additional endpoint: `<ipv4>:<port>`, `[<ipv6>]:<port>` or `unix:<path>`
+    #[arg(long)]
+    listen: Vec<String>,
+
     /// User login name
     #[arg(long)]
     login: Option<String>,
@@ -24,6 +28,10 @@ struct Args {
     /// User password
     #[arg(long)]
     password: Option<String>,
+
+    /// Host key file in OpenSSH format
+    #[arg(long)]
+    host_key_file: Option<String>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -49,6 +57,14 @@ async fn main() {
         builder = builder.port(port);
     }
 
+    for endpoint in &args.listen {
+        builder = builder.listen(endpoint);
+    }
+
+    if let Some(host_key_file) = &args.host_key_file {
+        builder = builder.host_key_file(host_key_file);
+    }
+
     let login = args.login.unwrap_or_else(|| "user".to_string());
     let pass = args.password.unwrap_or_else(|| "pass123".to_string());
 
@@ -56,6 +72,9 @@ async fn main() {
 
     let server = builder.run().await.unwrap();
     println!("Addr: {}", server.addr());
+    for endpoint in server.endpoints() {
+        println!("Endpoint: {endpoint}");
+    }
     println!("Login: {login}");
     println!("Password: {pass}");
     println!("Public Key: {}", server.server_public_key());
@@ -0,0 +1,335 @@
+use anyhow::{anyhow, Result};
+use russh::{cipher, compression, kex, mac, Preferred};
+use russh_keys::key;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::debug;
+
+/// SSH message number of the key exchange init packet.
+const MSG_KEXINIT: u8 = 20;
+
+/// Stop looking for client's key exchange init after so many bytes.
+const MAX_SNIFFED_BYTES: usize = 64 * 1024;
+
+/// Algorithms negotiated between the ssh server and a client.
+///
+/// russh doesn't expose the negotiated algorithms, so they are reconstructed
+/// from the client's key exchange init by repeating the negotiation rules of
+/// russh 0.46, including its quirks. The values may drift from what the
+/// server actually uses if russh changes those rules, which is why the
+/// dependency is pinned to `~0.46`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NegotiatedAlgorithms {
+    /// Id of the connection.
+    pub connection_id: usize,
+    /// Key exchange algorithm.
+    pub kex: String,
+    /// Host key algorithm.
+    pub host_key: String,
+    /// Symmetric cipher of both directions. The server picks it from the
+    /// client to server list and uses it for the server to client direction too.
+    pub cipher: String,
+    /// MAC algorithm of the client to server direction, `none` when
+    /// an AEAD cipher is used and there is no common MAC algorithm.
+    pub mac_client_to_server: String,
+    /// MAC algorithm of the server to client direction.
+    pub mac_server_to_client: String,
+    /// Compression of the client to server direction.
+    pub compression_client_to_server: String,
+    /// Compression of the server to client direction.
+    pub compression_server_to_client: String,
+}
+
+/// Negotiated algorithms of all connections.
+/// Key of the hash map is a connection id.
+pub(crate) type NegotiatedMap = Arc<Mutex<HashMap<usize, NegotiatedAlgorithms>>>;
+
+/// Algorithm lists configured in the builder.
+#[derive(Default)]
+pub(crate) struct AlgorithmLists {
+    pub kex: Option<Vec<String>>,
+    pub host_key: Option<Vec<String>>,
+    pub cipher: Option<Vec<String>>,
+    pub mac: Option<Vec<String>>,
+    pub compression: Option<Vec<String>>,
+}
+
+impl AlgorithmLists {
+    /// Build russh preferences. `host_keys` are names of available host keys in preference order.
+    pub fn preferred(&self, host_keys: Vec<key::Name>) -> Result<Preferred> {
+        let mut preferred = Preferred::default();
+
+        if let Some(names) = &self.kex {
+            let mut kex = parse_names::<kex::Name>(names, "kex")?;
+            // Keep protocol extensions negotiated through the kex list.
+            kex.push(kex::EXTENSION_SUPPORT_AS_SERVER);
+            kex.push(kex::EXTENSION_OPENSSH_STRICT_KEX_AS_SERVER);
+            preferred.kex = Cow::Owned(kex);
+        }
+
+        preferred.key = match &self.host_key {
+            Some(names) => Cow::Owned(parse_names::<key::Name>(names, "host key")?),
+            None => Cow::Owned(host_keys),
+        };
+
+        if let Some(names) = &self.cipher {
+            preferred.cipher = Cow::Owned(parse_names::<cipher::Name>(names, "cipher")?);
+        }
+        if let Some(names) = &self.mac {
+            preferred.mac = Cow::Owned(parse_names::<mac::Name>(names, "mac")?);
+        }
+        if let Some(names) = &self.compression {
+            preferred.compression =
+                Cow::Owned(parse_names::<compression::Name>(names, "compression")?);
+        }
+
+        Ok(preferred)
+    }
+}
+
+fn parse_names<'a, N: TryFrom<&'a str>>(names: &'a [String], kind: &str) -> Result<Vec<N>> {
+    names
+        .iter()
+        .map(|n| N::try_from(n.as_str()).map_err(|_| anyhow!("Unsupported {kind} algorithm {n}")))
+        .collect()
+}
+
+/// Server side algorithm lists used to compute negotiated algorithms.
+#[derive(Debug)]
+pub(crate) struct ServerAlgorithms {
+    kex: Vec<String>,
+    host_key: Vec<String>,
+    cipher: Vec<String>,
+    mac: Vec<String>,
+    compression: Vec<String>,
+}
+
+impl ServerAlgorithms {
+    pub fn new(config: &russh::server::Config) -> Self {
+        let pseudo = [
+            kex::EXTENSION_SUPPORT_AS_CLIENT,
+            kex::EXTENSION_SUPPORT_AS_SERVER,
+            kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT,
+            kex::EXTENSION_OPENSSH_STRICT_KEX_AS_SERVER,
+        ];
+        let preferred = &config.preferred;
+        Self {
+            kex: names(preferred.kex.iter().filter(|k| !pseudo.contains(k))),
+            host_key: names(
+                preferred
+                    .key
+                    .iter()
+                    .filter(|n| config.keys.iter().any(|k| k.name() == n.0)),
+            ),
+            cipher: names(preferred.cipher.iter()),
+            mac: names(preferred.mac.iter()),
+            compression: names(preferred.compression.iter()),
+        }
+    }
+
+    /// Negotiate algorithms like the server does: first algorithm on the
+    /// client's list which is supported by the server wins.
+    ///
+    /// Returns [None] when the negotiation fails.
+    fn negotiate(&self, connection_id: usize, kexinit: &[u8]) -> Option<NegotiatedAlgorithms> {
+        let mut lists = NameLists::new(kexinit.get(17..)?);
+        let mut next = |server: &[String]| lists.next().map(|client| select(server, &client));
+
+        let kex = next(&self.kex)??;
+        let host_key = next(&self.host_key)??;
+        let cipher = next(&self.cipher)??;
+        // russh uses the client to server cipher in both directions.
+        next(&self.cipher)?;
+        let mut mac = || match next(&self.mac)? {
+            Some(mac) => Some(mac),
+            None if needs_mac(&cipher) => None,
+            None => Some("none".to_string()),
+        };
+        let mac_client_to_server = mac()?;
+        let mac_server_to_client = mac()?;
+        Some(NegotiatedAlgorithms {
+            connection_id,
+            kex,
+            host_key,
+            mac_client_to_server,
+            mac_server_to_client,
+            compression_client_to_server: next(&self.compression)??,
+            compression_server_to_client: next(&self.compression)??,
+            cipher,
+        })
+    }
+}
+
+/// Return true when the cipher doesn't authenticate packets by itself.
+fn needs_mac(cipher: &str) -> bool {
+    [
+        cipher::TRIPLE_DES_CBC,
+        cipher::AES_128_CBC,
+        cipher::AES_192_CBC,
+        cipher::AES_256_CBC,
+        cipher::AES_128_CTR,
+        cipher::AES_192_CTR,
+        cipher::AES_256_CTR,
+    ]
+    .iter()
+    .any(|c| c.as_ref() == cipher)
+}
+
+fn names<N: AsRef<str>>(names: impl Iterator<Item = N>) -> Vec<String> {
+    names.map(|n| n.as_ref().to_string()).collect()
+}
+
+fn select(server: &[String], client: &[String]) -> Option<String> {
+    client.iter().find(|c| server.contains(c)).cloned()
+}
+
+/// Iterator over name-lists of the key exchange init payload.
+struct NameLists<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> NameLists<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl Iterator for NameLists<'_> {
+    type Item = Vec<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = u32::from_be_bytes(self.data.get(..4)?.try_into().ok()?) as usize;
+        let list = self.data.get(4..4 + len)?;
+        self.data = &self.data[4 + len..];
+        Some(
+            String::from_utf8_lossy(list)
+                .split(',')
+                .filter(|n| !n.is_empty())
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+}
+
+/// Find the first packet after the client's identification line.
+///
+/// Lines before the identification are skipped, lines may end with LF only.
+fn packet_start(buf: &[u8]) -> Option<usize> {
+    let mut line_start = 0;
+    loop {
+        let line = &buf[line_start..];
+        let line_end = line_start + line.iter().position(|b| *b == b'\n')? + 1;
+        if line.starts_with(b"SSH-") {
+            return Some(line_end);
+        }
+        line_start = line_end;
+    }
+}
+
+/// Stream wrapper that watches the client's key exchange init packet
+/// and records algorithms negotiated in the connection.
+pub(crate) struct KexSniffer<S> {
+    inner: S,
+    connection_id: usize,
+    algorithms: Arc<ServerAlgorithms>,
+    negotiated: NegotiatedMap,
+    buf: Option<Vec<u8>>,
+}
+
+impl<S> KexSniffer<S> {
+    pub fn new(
+        inner: S,
+        connection_id: usize,
+        algorithms: Arc<ServerAlgorithms>,
+        negotiated: NegotiatedMap,
+    ) -> Self {
+        Self {
+            inner,
+            connection_id,
+            algorithms,
+            negotiated,
+            buf: Some(Vec::new()),
+        }
+    }
+
+    fn sniff(&mut self, data: &[u8]) {
+        let Some(buf) = &mut self.buf else {
+            return;
+        };
+        buf.extend_from_slice(data);
+
+        let Some(packet_start) = packet_start(buf) else {
+            if buf.len() > MAX_SNIFFED_BYTES {
+                self.buf = None;
+            }
+            return;
+        };
+        let packet = &buf[packet_start..];
+        let Some(len) = packet.get(..4) else {
+            return;
+        };
+        let packet_len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        if packet_len > MAX_SNIFFED_BYTES {
+            self.buf = None;
+            return;
+        }
+        let Some(packet) = packet.get(4..4 + packet_len) else {
+            return;
+        };
+
+        let padding_len = packet[0] as usize;
+        let payload = packet.get(1..packet_len.saturating_sub(padding_len));
+        if let Some(payload) = payload.filter(|p| p.first() == Some(&MSG_KEXINIT)) {
+            if let Some(negotiated) = self.algorithms.negotiate(self.connection_id, payload) {
+                debug!(
+                    connection_id = self.connection_id,
+                    "negotiated algorithms {negotiated:?}"
+                );
+                self.negotiated
+                    .lock()
+                    .unwrap()
+                    .insert(self.connection_id, negotiated);
+            }
+        }
+        self.buf = None;
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for KexSniffer<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if self.buf.is_some() && buf.filled().len() > before {
+            let data = buf.filled()[before..].to_vec();
+            self.sniff(&data);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for KexSniffer<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,332 @@
+use crate::ServerEvent;
+use anyhow::{anyhow, bail, Result};
+use russh::MethodSet;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+
+/// History of connections, authentication attempts and commands.
+#[derive(Clone, Debug, Default)]
+pub struct AuditLog {
+    /// Accepted connections, in order.
+    pub connections: Vec<ConnectionRecord>,
+    /// Authentication attempts of all connections, in order.
+    pub auth_attempts: Vec<AuthRecord>,
+    /// Commands run in exec channels and interactive shells, in order of start.
+    pub commands: Vec<CommandRecord>,
+}
+
+/// Connection accepted by the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionRecord {
+    /// Id of the connection.
+    pub connection_id: usize,
+    /// Address of the client.
+    pub peer_addr: Option<SocketAddr>,
+    /// Login of the authenticated user.
+    pub user: Option<String>,
+    /// Time when the connection was accepted.
+    pub connected_at: SystemTime,
+    /// Time when the connection was closed.
+    pub disconnected_at: Option<SystemTime>,
+}
+
+/// Result of an authentication attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthOutcome {
+    /// User authenticated.
+    Accepted,
+    /// Factor accepted but user has to pass the next step of
+    /// [User::set_required_auth_methods](crate::User::set_required_auth_methods).
+    /// The client gets a plain failure, without the partial success flag.
+    StepPassed,
+    /// Factor rejected.
+    Rejected,
+}
+
+/// Authentication attempt of a client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthRecord {
+    /// Id of the connection.
+    pub connection_id: usize,
+    /// Login sent by the client.
+    pub user: String,
+    /// Authentication method.
+    pub method: MethodSet,
+    /// Result of the attempt.
+    pub outcome: AuthOutcome,
+    /// Time of the attempt.
+    pub time: SystemTime,
+}
+
+/// Command run by a client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandRecord {
+    /// Id of the connection.
+    pub connection_id: usize,
+    /// Login of the user who run the command.
+    pub user: String,
+    /// Command line.
+    pub command: String,
+    /// True when the command was typed in an interactive shell.
+    pub shell: bool,
+    /// Exit code, [None] while the command is running.
+    pub exit_code: Option<u32>,
+    /// Time when the command started.
+    pub started_at: SystemTime,
+    /// Time when the command finished.
+    pub finished_at: Option<SystemTime>,
+}
+
+/// Capacity of the live event channel.
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Number of past events checked by [Audit::wait_for].
+const HISTORY_CAPACITY: usize = 1024;
+
+#[derive(Debug, Default)]
+struct AuditInner {
+    log: AuditLog,
+    /// Last events, oldest first.
+    history: VecDeque<ServerEvent>,
+}
+
+/// Audit log and event stream shared by all connections.
+#[derive(Clone, Debug)]
+pub(crate) struct Audit {
+    inner: Arc<Mutex<AuditInner>>,
+    events: broadcast::Sender<ServerEvent>,
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self {
+            inner: Arc::default(),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+        }
+    }
+}
+
+impl Audit {
+    pub fn log(&self) -> AuditLog {
+        self.inner.lock().unwrap().log.clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Wait for the first event matching the predicate. Past events are
+    /// checked first when `past` is true. Events are collected from the call,
+    /// not from the first poll of the returned future.
+    pub fn wait_for<P>(
+        &self,
+        mut predicate: P,
+        timeout: Duration,
+        past: bool,
+    ) -> impl Future<Output = Result<ServerEvent>>
+    where
+        P: FnMut(&ServerEvent) -> bool,
+    {
+        let (history, mut events) = {
+            let inner = self.inner.lock().unwrap();
+            let history = if past {
+                inner.history.clone()
+            } else {
+                VecDeque::new()
+            };
+            (history, self.events.subscribe())
+        };
+
+        async move {
+            if let Some(event) = history.into_iter().find(|e| predicate(e)) {
+                return Ok(event);
+            }
+
+            let wait = async {
+                loop {
+                    match events.recv().await {
+                        Ok(event) if predicate(&event) => return Ok(event),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => bail!("Ssh server stopped"),
+                    }
+                }
+            };
+            tokio::time::timeout(timeout, wait)
+                .await
+                .map_err(|_| anyhow!("Timed out waiting for server event"))?
+        }
+    }
+
+    fn emit(&self, inner: &mut AuditInner, event: ServerEvent) {
+        debug!("event {event:?}");
+        if inner.history.len() == HISTORY_CAPACITY {
+            inner.history.pop_front();
+        }
+        inner.history.push_back(event.clone());
+        let _ = self.events.send(event);
+    }
+
+    pub fn connected(&self, connection_id: usize, peer_addr: Option<SocketAddr>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.log.connections.push(ConnectionRecord {
+            connection_id,
+            peer_addr,
+            user: None,
+            connected_at: SystemTime::now(),
+            disconnected_at: None,
+        });
+        self.emit(
+            &mut inner,
+            ServerEvent::Connected {
+                connection_id,
+                peer_addr,
+            },
+        );
+    }
+
+    pub fn disconnected(&self, connection_id: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(connection) = inner.connection(connection_id) {
+            connection.disconnected_at = Some(SystemTime::now());
+        }
+        self.emit(&mut inner, ServerEvent::Disconnected { connection_id });
+    }
+
+    /// Authentication request arrived, the outcome is recorded with [Audit::auth].
+    pub fn auth_attempted(&self, connection_id: usize, user: &str, method: MethodSet) {
+        let mut inner = self.inner.lock().unwrap();
+        self.emit(
+            &mut inner,
+            ServerEvent::AuthAttempted {
+                connection_id,
+                user: user.to_string(),
+                method,
+            },
+        );
+    }
+
+    pub fn auth(&self, connection_id: usize, user: &str, method: MethodSet, outcome: AuthOutcome) {
+        let mut inner = self.inner.lock().unwrap();
+        if outcome == AuthOutcome::Accepted {
+            if let Some(connection) = inner.connection(connection_id) {
+                connection.user = Some(user.to_string());
+            }
+        }
+        inner.log.auth_attempts.push(AuthRecord {
+            connection_id,
+            user: user.to_string(),
+            method,
+            outcome,
+            time: SystemTime::now(),
+        });
+
+        let user = user.to_string();
+        let event = match outcome {
+            AuthOutcome::Accepted => ServerEvent::AuthSucceeded {
+                connection_id,
+                user,
+                method,
+            },
+            AuthOutcome::StepPassed | AuthOutcome::Rejected => ServerEvent::AuthFailed {
+                connection_id,
+                user,
+                method,
+                step_passed: outcome == AuthOutcome::StepPassed,
+            },
+        };
+        self.emit(&mut inner, event);
+    }
+
+    pub fn stopped(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        self.emit(&mut inner, ServerEvent::Stopped);
+    }
+
+    pub fn restarted(&self, downtime: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        self.emit(&mut inner, ServerEvent::Restarted { downtime });
+    }
+
+    pub fn channel_opened(&self, connection_id: usize, channel_type: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        self.emit(
+            &mut inner,
+            ServerEvent::ChannelOpened {
+                connection_id,
+                channel_type: channel_type.to_string(),
+            },
+        );
+    }
+
+    pub fn pty_requested(&self, connection_id: usize, term: &str, col_width: u32, row_height: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        self.emit(
+            &mut inner,
+            ServerEvent::PtyRequested {
+                connection_id,
+                term: term.to_string(),
+                col_width,
+                row_height,
+            },
+        );
+    }
+
+    /// Record started command. Returns index of the record.
+    pub fn command_started(
+        &self,
+        connection_id: usize,
+        user: &str,
+        command: &str,
+        shell: bool,
+    ) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        inner.log.commands.push(CommandRecord {
+            connection_id,
+            user: user.to_string(),
+            command: command.to_string(),
+            shell,
+            exit_code: None,
+            started_at: SystemTime::now(),
+            finished_at: None,
+        });
+        self.emit(
+            &mut inner,
+            ServerEvent::CommandStarted {
+                connection_id,
+                user: user.to_string(),
+                command: command.to_string(),
+            },
+        );
+        inner.log.commands.len() - 1
+    }
+
+    pub fn command_finished(&self, index: usize, exit_code: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        let command = &mut inner.log.commands[index];
+        command.exit_code = Some(exit_code);
+        command.finished_at = Some(SystemTime::now());
+        let event = ServerEvent::CommandFinished {
+            connection_id: command.connection_id,
+            user: command.user.clone(),
+            command: command.command.clone(),
+            exit_code,
+        };
+        self.emit(&mut inner, event);
+    }
+}
+
+impl AuditInner {
+    fn connection(&mut self, connection_id: usize) -> Option<&mut ConnectionRecord> {
+        self.log
+            .connections
+            .iter_mut()
+            .rev()
+            .find(|c| c.connection_id == connection_id)
+    }
+}
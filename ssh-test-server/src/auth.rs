@@ -0,0 +1,125 @@
+use russh::server::Auth;
+use std::borrow::Cow;
+
+/// Script of a keyboard-interactive authentication.
+///
+/// Script consists of rounds. Every round is sent to the client as a single
+/// info request with one or more prompts. The client is authenticated when
+/// all answers in every round match.
+///
+/// # Example
+///
+/// ```
+/// use ssh_test_server::KeyboardInteractive;
+///
+/// let script = KeyboardInteractive::default()
+///     .instructions("Two factor authentication")
+///     .add_prompt("Password: ", false, "pass123")
+///     .next_round()
+///     .add_prompt("OTP code: ", true, "123456");
+///
+/// assert_eq!(script.rounds(), 2);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct KeyboardInteractive {
+    name: String,
+    instructions: String,
+    rounds: Vec<Vec<KeyboardInteractivePrompt>>,
+}
+
+#[derive(Clone, Debug)]
+struct KeyboardInteractivePrompt {
+    prompt: String,
+    echo: bool,
+    answer: String,
+}
+
+impl KeyboardInteractive {
+    /// Set name of the info requests.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Set instructions of the info requests.
+    pub fn instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = instructions.into();
+        self
+    }
+
+    /// Add prompt to the current round.
+    ///
+    /// When `echo` is true the client should display the typed answer.
+    pub fn add_prompt(
+        mut self,
+        prompt: impl Into<String>,
+        echo: bool,
+        answer: impl Into<String>,
+    ) -> Self {
+        if self.rounds.is_empty() {
+            self.rounds.push(Vec::new());
+        }
+        self.rounds
+            .last_mut()
+            .unwrap()
+            .push(KeyboardInteractivePrompt {
+                prompt: prompt.into(),
+                echo,
+                answer: answer.into(),
+            });
+        self
+    }
+
+    /// Start a new round. Following prompts will be sent in a separate info request.
+    pub fn next_round(mut self) -> Self {
+        self.rounds.push(Vec::new());
+        self
+    }
+
+    /// Number of rounds in the script.
+    pub fn rounds(&self) -> usize {
+        self.rounds.len()
+    }
+
+    pub(crate) fn info_request(&self, round: usize) -> Auth {
+        let prompts: Vec<_> = self.rounds[round]
+            .iter()
+            .map(|p| (Cow::Owned(p.prompt.clone()), p.echo))
+            .collect();
+        Auth::Partial {
+            name: Cow::Owned(self.name.clone()),
+            instructions: Cow::Owned(self.instructions.clone()),
+            prompts: Cow::Owned(prompts),
+        }
+    }
+
+    pub(crate) fn check_answers<'a>(
+        &self,
+        round: usize,
+        answers: impl Iterator<Item = &'a [u8]>,
+    ) -> bool {
+        let expected = &self.rounds[round];
+        let answers: Vec<_> = answers.collect();
+        answers.len() == expected.len()
+            && expected
+                .iter()
+                .zip(answers)
+                .all(|(p, a)| p.answer.as_bytes() == a)
+    }
+}
+
+/// Progress of keyboard-interactive authentication in a connection.
+#[derive(Debug)]
+pub(crate) struct KeyboardInteractiveState {
+    pub user: String,
+    pub script: KeyboardInteractive,
+    pub round: usize,
+}
+
+/// Progress of multi-factor authentication in a connection.
+#[derive(Debug)]
+pub(crate) struct AuthProgress {
+    pub user: String,
+    /// Index of the next required step.
+    pub step: usize,
+}
@@ -0,0 +1,127 @@
+use crate::{ServerEvent, SshServer, SshServerBuilder};
+use anyhow::{anyhow, Result};
+use std::ops::Deref;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use tokio::io::DuplexStream;
+use tokio::runtime;
+use tokio::sync::oneshot;
+
+/// Ssh server running in a background thread with its own runtime,
+/// for tests which don't use async.
+///
+/// Accessors of [SshServer] are available through [Deref]. Blocking
+/// counterparts exist for [SshServer::shutdown], [SshServer::stop],
+/// [SshServer::restart] and [SshServer::wait_for]. Other async methods, like
+/// [SshServer::wait_for_next] and [SshServer::open_remote_forward], need an
+/// async runtime and are not supported. Dropping the handle disconnects
+/// clients and shuts the runtime down.
+///
+/// # Example
+///
+/// ```
+/// use ssh_test_server::{SshServerBuilder, User};
+///
+/// let ssh = SshServerBuilder::default()
+///     .add_user(User::new("user", "pass123"))
+///     .run_blocking()
+///     .unwrap();
+///
+/// println!("ssh -p {} user@{}", ssh.port(), ssh.host());
+/// ssh.shutdown();
+/// ```
+#[derive(Debug)]
+pub struct BlockingSshServer {
+    server: Arc<SshServer>,
+    runtime: runtime::Handle,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl BlockingSshServer {
+    pub(crate) fn run(builder: SshServerBuilder) -> Result<Self> {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let handle = runtime.handle().clone();
+        let (started, started_rx) = mpsc::channel();
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let thread = thread::Builder::new()
+            .name("ssh-test-server".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let server = match builder.run().await {
+                        Ok(server) => Arc::new(server),
+                        Err(e) => {
+                            let _ = started.send(Err(e));
+                            return;
+                        }
+                    };
+                    let _ = started.send(Ok(server.clone()));
+                    let _ = shutdown_rx.await;
+                    server.shutdown().await;
+                });
+            })?;
+
+        let server = started_rx
+            .recv()
+            .map_err(|_| anyhow!("Ssh server thread stopped"))??;
+        Ok(Self {
+            server,
+            runtime: handle,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    /// Blocking version of [SshServer::shutdown].
+    pub fn shutdown(&self) {
+        self.runtime.block_on(self.server.shutdown());
+    }
+
+    /// Blocking version of [SshServer::stop].
+    pub fn stop(&self) {
+        self.runtime.block_on(self.server.stop());
+    }
+
+    /// Blocking version of [SshServer::restart].
+    pub fn restart(&self) -> Result<()> {
+        self.runtime.block_on(self.server.restart())
+    }
+
+    /// Blocking version of [SshServer::wait_for].
+    pub fn wait_for<P>(&self, predicate: P, timeout: Duration) -> Result<ServerEvent>
+    where
+        P: FnMut(&ServerEvent) -> bool,
+    {
+        self.runtime
+            .block_on(self.server.wait_for(predicate, timeout))
+    }
+
+    /// Same as [SshServer::connect_in_memory], the stream is served by the
+    /// background runtime.
+    pub fn connect_in_memory(&self) -> DuplexStream {
+        let _runtime = self.runtime.enter();
+        self.server.connect_in_memory()
+    }
+}
+
+impl Deref for BlockingSshServer {
+    type Target = SshServer;
+
+    fn deref(&self) -> &SshServer {
+        &self.server
+    }
+}
+
+impl Drop for BlockingSshServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
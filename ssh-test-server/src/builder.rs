@@ -1,18 +1,26 @@
-use crate::session::SshConnection;
+use crate::algorithms::{AlgorithmLists, NegotiatedMap, ServerAlgorithms};
+use crate::audit::Audit;
+use crate::blocking::BlockingSshServer;
+use crate::command::{Programs, RawProgram, SyncProgram};
+use crate::connections::Connections;
+use crate::forward::Forwarding;
+use crate::listener::{self, Acceptor, Endpoint};
 use crate::user::User;
-use crate::{SshExecuteHandler, SshServer};
-use anyhow::Result;
-use rand::Rng;
-use random_port::{PortPicker, Protocol};
+use crate::{
+    CommandPattern, Expectation, KeyboardInteractive, Listener, MemoryFs, SshExecuteHandler,
+    SshRawExecuteHandler, SshServer, SshStreamingProgram, TunnelStream,
+};
+use anyhow::{anyhow, bail, Result};
 use russh::{server, MethodSet};
 use russh_keys::key;
-use russh_keys::key::KeyPair;
-use std::borrow::Cow;
+use russh_keys::key::{KeyPair, SignatureHash};
 use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::net::TcpListener;
-use tracing::debug;
 
 /// Builder for the ssh server.
 ///
@@ -34,8 +42,46 @@ use tracing::debug;
 pub struct SshServerBuilder {
     port: Option<u16>,
     bind_addr: Option<String>,
+    listen: Vec<String>,
+    in_memory: bool,
     users: Vec<User>,
-    programs: HashMap<String, Box<SshExecuteHandler>>,
+    programs: Programs,
+    keyboard_interactive: Option<KeyboardInteractive>,
+    host_keys: Vec<HostKey>,
+    algorithms: AlgorithmLists,
+    fs: MemoryFs,
+    forwarding: Forwarding,
+}
+
+enum HostKey {
+    KeyPair(Box<KeyPair>),
+    File(PathBuf),
+}
+
+impl HostKey {
+    fn load(self) -> Result<KeyPair> {
+        match self {
+            HostKey::KeyPair(key) => Ok(*key),
+            HostKey::File(path) => Ok(russh_keys::load_secret_key(path, None)?),
+        }
+    }
+}
+
+/// Expand host key to variants for every signature algorithm supported by the key.
+///
+/// RSA keys are offered as `rsa-sha2-512`, `rsa-sha2-256` and `ssh-rsa`.
+fn host_key_variants(key: KeyPair) -> Vec<KeyPair> {
+    match key {
+        KeyPair::RSA { .. } => [
+            SignatureHash::SHA2_512,
+            SignatureHash::SHA2_256,
+            SignatureHash::SHA1,
+        ]
+        .into_iter()
+        .filter_map(|hash| key.with_signature_hash(hash))
+        .collect(),
+        key => vec![key],
+    }
 }
 
 impl SshServerBuilder {
@@ -84,6 +130,10 @@ impl SshServerBuilder {
 
     /// Add custom command/program.
     ///
+    /// Program is selected by its name, the first word of the command line.
+    /// Adding a program with the same name again replaces the handler.
+    /// See [SshServerBuilder::add_pattern_program] for the order of matching.
+    ///
     /// # Example
     ///
     /// ```
@@ -111,11 +161,467 @@ impl SshServerBuilder {
     /// # }
     /// ```
     pub fn add_program(mut self, program: &str, handler: Box<SshExecuteHandler>) -> Self {
-        self.programs.insert(program.to_string(), handler);
+        self.programs
+            .add_named(program, Box::new(SyncProgram(handler)));
+        self
+    }
+
+    /// Add custom command/program which gets the whole command line
+    /// instead of arguments split by the server.
+    ///
+    /// Handler is selected by the program name, the first word of the command line.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{SshExecuteContext, SshServerBuilder, SshExecuteResult};
+    /// fn cmd_sh(_context: &SshExecuteContext, command: &[u8]) -> SshExecuteResult {
+    ///     // Print the script passed in `sh -c '<script>'` as it was sent.
+    ///     let script = command.strip_prefix(b"sh -c ").unwrap_or_default();
+    ///     SshExecuteResult::stdout(0, script)
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .add_raw_program("sh", Box::new(cmd_sh))
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn add_raw_program(mut self, program: &str, handler: Box<SshRawExecuteHandler>) -> Self {
+        self.programs
+            .add_named(program, Box::new(RawProgram(handler)));
+        self
+    }
+
+    /// Add custom command/program with asynchronous handler.
+    ///
+    /// See [SshProgram](crate::SshProgram) and [SshStreamingProgram] for examples.
+    pub fn add_async_program(
+        mut self,
+        program: &str,
+        handler: impl SshStreamingProgram + 'static,
+    ) -> Self {
+        self.programs.add_named(program, Box::new(handler));
+        self
+    }
+
+    /// Add custom command/program selected by a pattern matched against the whole command line.
+    ///
+    /// Programs registered by name and by pattern are checked in the order of
+    /// registration, the first match wins. Registered programs take precedence
+    /// over built-in commands, including `scp`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{CommandPattern, SshServerBuilder, SshExecuteResult};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .add_pattern_program(
+    ///         CommandPattern::glob("systemctl status *"),
+    ///         Box::new(|_, _, _| SshExecuteResult::stdout(0, "active (running)\n")),
+    ///     )
+    ///     .add_pattern_program(
+    ///         CommandPattern::regex(r"systemctl (start|stop|restart) \w+").unwrap(),
+    ///         Box::new(|_, _, _| SshExecuteResult::stdout(0, "")),
+    ///     )
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn add_pattern_program(
+        mut self,
+        pattern: CommandPattern,
+        handler: Box<SshExecuteHandler>,
+    ) -> Self {
+        self.programs
+            .add_pattern(pattern, Box::new(SyncProgram(handler)));
+        self
+    }
+
+    /// Add custom command/program with asynchronous handler selected by a pattern.
+    ///
+    /// See [SshServerBuilder::add_pattern_program].
+    pub fn add_async_pattern_program(
+        mut self,
+        pattern: CommandPattern,
+        handler: impl SshStreamingProgram + 'static,
+    ) -> Self {
+        self.programs.add_pattern(pattern, Box::new(handler));
+        self
+    }
+
+    /// Set handler of commands not matched by any registered program or built-in command.
+    ///
+    /// By default such commands fail with `command not found` and exit code 127.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{SshServerBuilder, SshExecuteResult};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .fallback_program(Box::new(|_, program, _| {
+    ///         SshExecuteResult::stderr(1, format!("{program}: permission denied\n"))
+    ///     }))
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn fallback_program(mut self, handler: Box<SshExecuteHandler>) -> Self {
+        self.programs.fallback = Some(Box::new(SyncProgram(handler)));
+        self
+    }
+
+    /// Set asynchronous handler of commands not matched by any registered program.
+    ///
+    /// See [SshServerBuilder::fallback_program].
+    pub fn async_fallback_program(mut self, handler: impl SshStreamingProgram + 'static) -> Self {
+        self.programs.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Expect a command to be run by a client before the server starts,
+    /// so commands sent right after [SshServerBuilder::run] are matched too.
+    ///
+    /// `configure` sets up the [Expectation], see
+    /// [SshServer::expect_command](crate::SshServer::expect_command).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{SshExecuteResult, SshServerBuilder};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default()
+    ///     .expect_command("systemctl stop *", |e| e.times(2))
+    ///     .expect_command("reboot", |e| {
+    ///         e.returning(|_, _, _| SshExecuteResult::stdout(0, "Rebooting...\n"))
+    ///     })
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// assert!(ssh.verify().is_err());
+    /// # }
+    /// ```
+    pub fn expect_command<F>(self, command: &str, configure: F) -> Self
+    where
+        F: FnOnce(Expectation) -> Expectation,
+    {
+        configure(self.programs.expectations.add(command));
+        self
+    }
+
+    /// Enable keyboard-interactive authentication.
+    ///
+    /// The script is used for every user that doesn't have own script set by
+    /// [User::set_keyboard_interactive].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{KeyboardInteractive, SshServerBuilder, User};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .add_user(User::new("user", "pass"))
+    ///     .keyboard_interactive(
+    ///         KeyboardInteractive::default()
+    ///             .add_prompt("Password: ", false, "pass")
+    ///             .next_round()
+    ///             .add_prompt("Verification code: ", true, "123456"),
+    ///     )
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn keyboard_interactive(mut self, script: KeyboardInteractive) -> Self {
+        self.keyboard_interactive = Some(script);
+        self
+    }
+
+    /// Add host key instead of generating a new one on every start.
+    ///
+    /// It can be called multiple times to serve keys of different algorithms
+    /// (ed25519, RSA, ECDSA). Keys added first are preferred.
+    /// [SshServerBuilder::run] fails when two keys use the same algorithm.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// use russh_keys::key::KeyPair;
+    /// use russh_keys::PublicKeyBase64;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let key = KeyPair::generate_ed25519();
+    /// let public_key = format!("{} {}", key.name(), key.public_key_base64());
+    ///
+    /// let ssh = SshServerBuilder::default()
+    ///     .host_key(key)
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// assert_eq!(ssh.server_public_key(), public_key);
+    /// # }
+    /// ```
+    pub fn host_key(mut self, key: KeyPair) -> Self {
+        self.host_keys.push(HostKey::KeyPair(Box::new(key)));
+        self
+    }
+
+    /// Add host key loaded from a file in OpenSSH private key format.
+    ///
+    /// The file is read by [SshServerBuilder::run], which fails when
+    /// the key cannot be loaded.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .host_key_file("tests/fixtures/host_ed25519")
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn host_key_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.host_keys
+            .push(HostKey::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Add ed25519 host key derived from a seed.
+    ///
+    /// The seed is used as the ed25519 secret key, so the same seed always
+    /// gives the same server fingerprint.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh1 = SshServerBuilder::default()
+    ///     .host_key_seed([7; 32])
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// let ssh2 = SshServerBuilder::default()
+    ///     .host_key_seed([7; 32])
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// assert_eq!(ssh1.server_public_key(), ssh2.server_public_key());
+    /// # }
+    /// ```
+    pub fn host_key_seed(self, seed: [u8; 32]) -> Self {
+        let key = ed25519_dalek::SigningKey::from_bytes(&seed);
+        self.host_key(KeyPair::Ed25519(key))
+    }
+
+    /// Restrict and reorder key exchange algorithms offered by the server.
+    ///
+    /// [SshServerBuilder::run] fails when an algorithm is not supported.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .kex_algorithms(&["curve25519-sha256", "diffie-hellman-group14-sha256"])
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn kex_algorithms(mut self, algorithms: &[&str]) -> Self {
+        self.algorithms.kex = Some(to_strings(algorithms));
+        self
+    }
+
+    /// Restrict and reorder host key algorithms offered by the server.
+    ///
+    /// Only algorithms with a matching host key are offered.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .host_key_algorithms(&["ssh-ed25519"])
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn host_key_algorithms(mut self, algorithms: &[&str]) -> Self {
+        self.algorithms.host_key = Some(to_strings(algorithms));
+        self
+    }
+
+    /// Restrict and reorder ciphers offered by the server.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .cipher_algorithms(&["aes256-gcm@openssh.com", "aes128-ctr"])
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn cipher_algorithms(mut self, algorithms: &[&str]) -> Self {
+        self.algorithms.cipher = Some(to_strings(algorithms));
         self
     }
 
-    /// Listen on address.
+    /// Restrict and reorder MAC algorithms offered by the server.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .mac_algorithms(&["hmac-sha2-256-etm@openssh.com"])
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn mac_algorithms(mut self, algorithms: &[&str]) -> Self {
+        self.algorithms.mac = Some(to_strings(algorithms));
+        self
+    }
+
+    /// Restrict and reorder compression algorithms offered by the server.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .compression_algorithms(&["zlib@openssh.com", "none"])
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn compression_algorithms(mut self, algorithms: &[&str]) -> Self {
+        self.algorithms.compression = Some(to_strings(algorithms));
+        self
+    }
+
+    /// Serve the in-memory filesystem over SFTP.
+    ///
+    /// Server starts with an empty filesystem when not set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{MemoryFs, SshServerBuilder};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let fs = MemoryFs::new();
+    /// fs.write("/home/user/.profile", "export EDITOR=vi").unwrap();
+    ///
+    /// let ssh = SshServerBuilder::default().fs(fs).run().await.unwrap();
+    ///
+    /// assert!(ssh.fs().exists("/home/user/.profile"));
+    /// # }
+    /// ```
+    pub fn fs(mut self, fs: MemoryFs) -> Self {
+        self.fs = fs;
+        self
+    }
+
+    /// Add virtual tcp endpoint reachable with local port forwarding (`ssh -L`).
+    ///
+    /// `host` and `port` have to match the target requested by the client,
+    /// `host` is compared as a string, so IPv6 addresses are given without
+    /// brackets. The handler gets a bidirectional stream of the tunnel,
+    /// the channel is closed when the handler finishes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .add_tcp_endpoint("db.internal", 5432, |mut stream| async move {
+    ///         stream.write_all(b"hello from database").await.unwrap();
+    ///     })
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn add_tcp_endpoint<F, Fut>(mut self, host: &str, port: u16, handler: F) -> Self
+    where
+        F: Fn(TunnelStream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.forwarding.endpoints.insert(
+            (host.to_string(), port.into()),
+            Arc::new(move |stream| Box::pin(handler(stream))),
+        );
+        self
+    }
+
+    /// Connect to real tcp targets of local port forwarding when no virtual
+    /// endpoint matches. Disabled by default, so such connections are rejected.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .tcp_forwarding_fallback(true)
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn tcp_forwarding_fallback(mut self, enabled: bool) -> Self {
+        self.forwarding.tcp_fallback = enabled;
+        self
+    }
+
+    /// Listen on address, IP or hostname. IPv6 addresses may be in brackets.
+    ///
+    /// Default is `127.0.0.1`. See [SshServerBuilder::listen] for more endpoints.
     ///
     /// # Example
     ///
@@ -136,6 +642,10 @@ impl SshServerBuilder {
 
     /// Listen on port.
     ///
+    /// By default the system assigns a free port, see [SshServer::port](crate::SshServer::port).
+    /// [SshServerBuilder::run] fails with [SshServerError::AddrInUse](crate::SshServerError::AddrInUse)
+    /// when the port is taken.
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -153,38 +663,124 @@ impl SshServerBuilder {
         self
     }
 
+    /// Listen on additional endpoint: `<ipv4>:<port>`, `[<ipv6>]:<port>` or
+    /// `unix:<path>`. Port 0 lets the system pick a free port. Unspecified
+    /// address `[::]` is dual-stack and accepts IPv4 clients too.
+    ///
+    /// When only `listen` endpoints are set, the server doesn't listen on
+    /// [SshServerBuilder::bind_addr] and [SshServerBuilder::port].
+    /// Bound endpoints are reported by [SshServer::endpoints](crate::SshServer::endpoints).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let _ssh = SshServerBuilder::default()
+    ///     .listen("[::]:0")
+    ///     .listen("unix:/tmp/ssh-test-server.sock")
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn listen(mut self, endpoint: &str) -> Self {
+        self.listen.push(endpoint.to_string());
+        self
+    }
+
+    /// Don't listen on [SshServerBuilder::bind_addr] and [SshServerBuilder::port],
+    /// clients connect with [SshServer::connect_in_memory](crate::SshServer::connect_in_memory).
+    ///
+    /// Endpoints added with [SshServerBuilder::listen] are still bound.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().in_memory().run().await.unwrap();
+    ///
+    /// assert!(ssh.endpoints().is_empty());
+    /// let _stream = ssh.connect_in_memory();
+    /// # }
+    /// ```
+    pub fn in_memory(mut self) -> Self {
+        self.in_memory = true;
+        self
+    }
+
+    /// Build and run the ssh server in a background thread with its own
+    /// runtime, for tests which don't use async.
+    ///
+    /// Must not be called from async context. Server stops when
+    /// [BlockingSshServer] is dropped.
+    pub fn run_blocking(self) -> Result<BlockingSshServer> {
+        BlockingSshServer::run(self)
+    }
+
     /// Build and run the ssh server.
     ///
     /// Server stops when [SshServer] is dropped.
     pub async fn run(self) -> Result<SshServer> {
-        let host = self
-            .bind_addr
-            .clone()
-            .unwrap_or_else(|| "127.0.0.1".to_string());
-
-        let port_picker = PortPicker::new()
-            .host(host.clone())
-            .protocol(Protocol::Tcp)
-            .random(true);
-        let port = self.port.unwrap_or_else(|| {
-            port_picker.pick().unwrap_or_else(|_| {
-                let mut rng = rand::thread_rng();
-                rng.gen_range(15000..55000)
-            })
-        });
-        let addr = format!("{host}:{port}");
+        let mut endpoints = vec![];
+        let mut host = None;
+        if !self.in_memory
+            && (self.listen.is_empty() || self.bind_addr.is_some() || self.port.is_some())
+        {
+            let bind_addr = self.bind_addr.as_deref().unwrap_or("127.0.0.1");
+            let bind_addr = bind_addr.trim_start_matches('[').trim_end_matches(']');
+            endpoints.push(Endpoint::Tcp(
+                resolve(bind_addr, self.port.unwrap_or(0)).await?,
+            ));
+            host = Some(bind_addr.to_string());
+        }
+        for endpoint in &self.listen {
+            endpoints.push(endpoint.parse()?);
+        }
 
-        let server_keys = KeyPair::generate_ed25519();
-        let server_public_key = server_keys.clone_public_key()?;
+        let host_keys = if self.host_keys.is_empty() {
+            vec![KeyPair::generate_ed25519()]
+        } else {
+            self.host_keys
+                .into_iter()
+                .map(HostKey::load)
+                .collect::<Result<_>>()?
+        };
+        let server_public_keys = host_keys
+            .iter()
+            .map(KeyPair::clone_public_key)
+            .collect::<Result<_, _>>()?;
+
+        let mut methods = MethodSet::PASSWORD | MethodSet::PUBLICKEY;
+        if self.keyboard_interactive.is_some()
+            || self
+                .users
+                .iter()
+                .any(|u| u.keyboard_interactive().is_some())
+        {
+            methods |= MethodSet::KEYBOARD_INTERACTIVE;
+        }
 
         let mut config = server::Config {
-            methods: MethodSet::PASSWORD,
+            methods,
             auth_rejection_time: Duration::from_secs(0),
             ..Default::default()
         };
-        config.preferred.key = Cow::Borrowed(&[key::ED25519]);
-        config.keys.push(server_keys);
-        let config = Arc::new(config);
+        let mut key_names = vec![];
+        for key in host_keys.into_iter().flat_map(host_key_variants) {
+            let name = key::Name::try_from(key.name())
+                .map_err(|_| anyhow!("Unsupported host key algorithm {}", key.name()))?;
+            if key_names.contains(&name) {
+                bail!("Duplicate host key algorithm {}", key.name());
+            }
+            key_names.push(name);
+            config.keys.push(key);
+        }
+        config.preferred = self.algorithms.preferred(key_names)?;
+        let server_algorithms = Arc::new(ServerAlgorithms::new(&config));
         let users: Arc<Mutex<HashMap<String, User>>> = Arc::new(Mutex::new(
             self.users
                 .into_iter()
@@ -192,28 +788,57 @@ impl SshServerBuilder {
                 .collect(),
         ));
 
-        let socket = TcpListener::bind(addr).await?;
-        let users2 = users.clone();
-
-        let listener = tokio::spawn(async move {
-            let programs = Arc::new(self.programs);
-            let mut id = 0;
-            while let Ok((socket, addr)) = socket.accept().await {
-                let config = config.clone();
-                debug!("New connection from {addr:?}");
-                let s = SshConnection::new(id, users2.clone(), programs.clone());
-                tokio::spawn(server::run_stream(config, socket, s));
-                id += 1;
-            }
-            debug!("ssh server stopped");
+        let (sockets, endpoints): (Vec<_>, Vec<_>) =
+            listener::bind_all(&endpoints)?.into_iter().unzip();
+        let first_tcp = endpoints.iter().find_map(|e| match e {
+            Endpoint::Tcp(addr) => Some(*addr),
+            Endpoint::Unix(_) => None,
         });
+        let (host, port) = match first_tcp {
+            Some(addr) => (host.unwrap_or_else(|| addr.ip().to_string()), addr.port()),
+            None => (String::new(), 0),
+        };
+        let acceptor = Arc::new(Acceptor {
+            config: Arc::new(config),
+            server_algorithms,
+            negotiated: NegotiatedMap::default(),
+            users,
+            programs: Arc::new(self.programs),
+            keyboard_interactive: Arc::new(self.keyboard_interactive),
+            fs: self.fs,
+            forwarding: Arc::new(self.forwarding),
+            audit: Audit::default(),
+            connections: Connections::default(),
+            next_id: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+        });
+        let listener = Listener {
+            tasks: sockets.into_iter().map(|s| acceptor.listen(s)).collect(),
+            stopped_at: None,
+        };
 
         Ok(SshServer {
-            listener,
-            users,
+            listener: Mutex::new(listener),
+            acceptor,
             port,
             host,
-            server_public_key,
+            endpoints,
+            server_public_keys,
         })
     }
 }
+
+/// Resolve IP address or hostname.
+async fn resolve(host: &str, port: u16) -> Result<SocketAddr> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("Failed to resolve {host}"))
+}
+
+fn to_strings(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
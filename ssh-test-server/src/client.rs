@@ -0,0 +1,329 @@
+//! In-process ssh client for tests, enabled by the `client` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use ssh_test_server::{SshServerBuilder, User};
+//! use std::time::Duration;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let ssh = SshServerBuilder::default()
+//!     .add_user(User::new("user", "pass123"))
+//!     .run()
+//!     .await
+//!     .unwrap();
+//! let client = ssh.client("user").await.unwrap();
+//!
+//! let output = client.exec("echo hello").await.unwrap();
+//! assert_eq!(output.stdout, b"hello\n");
+//!
+//! let mut shell = client.shell().await.unwrap();
+//! shell.expect(r"\$ ", Duration::from_secs(1)).await.unwrap();
+//! shell.send_line("echo hello").await.unwrap();
+//! shell
+//!     .expect(r"\r\nhello\r\n", Duration::from_secs(1))
+//!     .await
+//!     .unwrap();
+//! # }
+//! ```
+use crate::{SshServer, User};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use regex::bytes::Regex;
+use russh::client::{self, Handle, Msg};
+use russh::{Channel, ChannelMsg, ChannelStream, Disconnect};
+use russh_keys::key::{KeyPair, PublicKey};
+use russh_sftp::client::SftpSession;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+
+impl SshServer {
+    /// Connect a client in memory and authenticate as `user` with the user's password.
+    ///
+    /// Client accepts only host keys of this server. Use [SshServer::client_with_key]
+    /// for users who authenticate with a public key. Users who require several
+    /// authentication steps, see [User::set_required_auth_methods](crate::User::set_required_auth_methods),
+    /// are not supported.
+    pub async fn client(&self, user: &str) -> Result<SshClient> {
+        let password = self
+            .client_user(user)?
+            .map(|u| u.password().to_string())
+            .ok_or_else(|| anyhow!("Unknown user {user}"))?;
+
+        let mut client = self.connect_client().await?;
+        if !client.handle.authenticate_password(user, password).await? {
+            bail!(
+                "Password authentication of {user} failed, use SshServer::client_with_key \
+                 if the user requires a public key"
+            );
+        }
+        Ok(client)
+    }
+
+    /// Same as [SshServer::client] but authenticate with the private `key`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use russh_keys::key::KeyPair;
+    /// use ssh_test_server::{SshServerBuilder, User};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let key = KeyPair::generate_ed25519();
+    /// let mut user = User::new("user", "pass123");
+    /// user.add_authorized_key(key.clone_public_key().unwrap());
+    /// let ssh = SshServerBuilder::default().add_user(user).run().await.unwrap();
+    ///
+    /// let client = ssh.client_with_key("user", key).await.unwrap();
+    /// assert_eq!(client.exec("echo hello").await.unwrap().stdout, b"hello\n");
+    /// # }
+    /// ```
+    pub async fn client_with_key(&self, user: &str, key: KeyPair) -> Result<SshClient> {
+        if self.client_user(user)?.is_none() {
+            bail!("Unknown user {user}");
+        }
+
+        let mut client = self.connect_client().await?;
+        if !client
+            .handle
+            .authenticate_publickey(user, Arc::new(key))
+            .await?
+        {
+            bail!("Public key authentication of {user} failed, the key is not authorized");
+        }
+        Ok(client)
+    }
+
+    /// Find user authenticated by the client. Fails for users who require
+    /// several authentication steps.
+    fn client_user(&self, user: &str) -> Result<Option<User>> {
+        let user = self.users().lock().unwrap().get(user).cloned();
+        match user {
+            Some(u) if u.required_auth_methods().len() > 1 => bail!(
+                "User {} requires multiple authentication steps, which the client doesn't support",
+                u.login()
+            ),
+            user => Ok(user),
+        }
+    }
+
+    /// Connect a client which is not authenticated yet.
+    async fn connect_client(&self) -> Result<SshClient> {
+        let (forwarded, forwarded_rx) = mpsc::unbounded_channel();
+        let handler = ClientHandler {
+            server_public_keys: self.server_public_keys.clone(),
+            forwarded,
+        };
+        let config = Arc::new(client::Config::default());
+        let handle = client::connect_stream(config, self.connect_in_memory(), handler).await?;
+        Ok(SshClient {
+            handle,
+            forwarded: Mutex::new(forwarded_rx),
+        })
+    }
+}
+
+/// Authenticated ssh client, see [SshServer::client] and [SshServer::client_with_key].
+pub struct SshClient {
+    handle: Handle<ClientHandler>,
+    forwarded: Mutex<mpsc::UnboundedReceiver<ForwardedConnection>>,
+}
+
+impl fmt::Debug for SshClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SshClient").finish_non_exhaustive()
+    }
+}
+
+/// Output of a command run with [SshClient::exec].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    /// Standard output.
+    pub stdout: Vec<u8>,
+    /// Standard error.
+    pub stderr: Vec<u8>,
+    /// Exit code, [None] when the server didn't send it.
+    pub status: Option<u32>,
+}
+
+/// Connection accepted on a port forwarded with [SshClient::request_remote_forward].
+pub struct ForwardedConnection {
+    /// Data of the connection.
+    pub stream: ChannelStream<Msg>,
+    /// Address of the forwarded port.
+    pub connected_address: String,
+    /// Forwarded port.
+    pub connected_port: u32,
+    /// Address of the peer which connected to the forwarded port.
+    pub originator_address: String,
+    /// Port of the peer which connected to the forwarded port.
+    pub originator_port: u32,
+}
+
+impl SshClient {
+    /// Run the command and collect its output.
+    pub async fn exec(&self, command: &str) -> Result<Output> {
+        let mut channel = self.handle.channel_open_session().await?;
+        channel.exec(true, command).await?;
+
+        let mut output = Output {
+            stdout: vec![],
+            stderr: vec![],
+            status: None,
+        };
+        while let Some(msg) = channel.wait().await {
+            match msg {
+                ChannelMsg::Data { data } => output.stdout.extend_from_slice(&data),
+                ChannelMsg::ExtendedData { data, ext: 1 } => output.stderr.extend_from_slice(&data),
+                ChannelMsg::ExitStatus { exit_status } => output.status = Some(exit_status),
+                _ => {}
+            }
+        }
+        Ok(output)
+    }
+
+    /// Open an interactive shell with a pseudo terminal.
+    pub async fn shell(&self) -> Result<Shell> {
+        let channel = self.handle.channel_open_session().await?;
+        channel
+            .request_pty(true, "xterm", 80, 24, 0, 0, &[])
+            .await?;
+        channel.request_shell(true).await?;
+        Ok(Shell {
+            channel,
+            buffer: vec![],
+        })
+    }
+
+    /// Start SFTP session.
+    pub async fn sftp(&self) -> Result<SftpSession> {
+        let channel = self.handle.channel_open_session().await?;
+        channel.request_subsystem(true, "sftp").await?;
+        Ok(SftpSession::new(channel.into_stream()).await?)
+    }
+
+    /// Open a connection to `host` and `port` through the server,
+    /// like local port forwarding.
+    pub async fn forward_local(&self, host: &str, port: u32) -> Result<ChannelStream<Msg>> {
+        let channel = self
+            .handle
+            .channel_open_direct_tcpip(host, port, "127.0.0.1", 0)
+            .await?;
+        Ok(channel.into_stream())
+    }
+
+    /// Ask the server to forward connections from `address` and `port` to the client.
+    /// Returns the forwarded port, the server picks one when `port` is 0.
+    ///
+    /// Connections are received with [SshClient::accept_forwarded].
+    pub async fn request_remote_forward(&mut self, address: &str, port: u32) -> Result<u32> {
+        Ok(self.handle.tcpip_forward(address, port).await?)
+    }
+
+    /// Wait for the next connection on a remotely forwarded port.
+    pub async fn accept_forwarded(&self, timeout: Duration) -> Result<ForwardedConnection> {
+        let mut forwarded = self.forwarded.lock().await;
+        tokio::time::timeout(timeout, forwarded.recv())
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for forwarded connection"))?
+            .ok_or_else(|| anyhow!("Client disconnected"))
+    }
+
+    /// Close the connection.
+    pub async fn disconnect(&self) -> Result<()> {
+        self.handle
+            .disconnect(Disconnect::ByApplication, "", "")
+            .await?;
+        Ok(())
+    }
+}
+
+/// Interactive shell opened with [SshClient::shell].
+pub struct Shell {
+    channel: Channel<Msg>,
+    /// Output received but not consumed by [Shell::expect] yet.
+    buffer: Vec<u8>,
+}
+
+impl Shell {
+    /// Send raw bytes, for example `b"\x03"` for Ctrl + C.
+    pub async fn send(&self, data: &[u8]) -> Result<()> {
+        self.channel.data(data).await?;
+        Ok(())
+    }
+
+    /// Send line of input followed by a newline.
+    pub async fn send_line(&self, line: &str) -> Result<()> {
+        self.send(format!("{line}\n").as_bytes()).await
+    }
+
+    /// Wait for output matching the regular expression.
+    ///
+    /// Returns output up to the end of the match, the rest is kept
+    /// for the next call.
+    pub async fn expect(&mut self, pattern: &str, timeout: Duration) -> Result<String> {
+        let regex = Regex::new(pattern)?;
+        let wait = async {
+            loop {
+                if let Some(m) = regex.find(&self.buffer) {
+                    let output: Vec<_> = self.buffer.drain(..m.end()).collect();
+                    return Ok(String::from_utf8_lossy(&output).into_owned());
+                }
+                match self.channel.wait().await {
+                    Some(ChannelMsg::Data { data }) => self.buffer.extend_from_slice(&data),
+                    Some(ChannelMsg::ExtendedData { data, .. }) => {
+                        self.buffer.extend_from_slice(&data)
+                    }
+                    Some(_) => {}
+                    None => bail!("Shell closed"),
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(result) => result.map_err(|e| self.expect_error(pattern, e)),
+            Err(_) => Err(self.expect_error(pattern, anyhow!("Timed out"))),
+        }
+    }
+
+    fn expect_error(&self, pattern: &str, e: anyhow::Error) -> anyhow::Error {
+        let output = String::from_utf8_lossy(&self.buffer);
+        anyhow!("{e} waiting for `{pattern}`, got: {output:?}")
+    }
+}
+
+struct ClientHandler {
+    server_public_keys: Vec<PublicKey>,
+    forwarded: mpsc::UnboundedSender<ForwardedConnection>,
+}
+
+#[async_trait]
+impl client::Handler for ClientHandler {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, Self::Error> {
+        Ok(self.server_public_keys.contains(key))
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: Channel<Msg>,
+        connected_address: &str,
+        connected_port: u32,
+        originator_address: &str,
+        originator_port: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let _ = self.forwarded.send(ForwardedConnection {
+            stream: channel.into_stream(),
+            connected_address: connected_address.to_string(),
+            connected_port,
+            originator_address: originator_address.to_string(),
+            originator_port,
+        });
+        Ok(())
+    }
+}
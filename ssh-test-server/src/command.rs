@@ -1,31 +1,247 @@
-use crate::session::ProgramsMap;
-use crate::{SshExecuteContext, UsersMap};
+use crate::audit::Audit;
+use crate::expect::Expectations;
+use crate::scp::Scp;
+use crate::{
+    MemoryFs, SshExecuteContext, SshExecuteHandler, SshExecuteIo, SshExecuteResult, SshProgram,
+    SshRawExecuteHandler, SshStreamingProgram, UsersMap,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use regex::Regex;
 use russh::server::Handle;
-use russh::{ChannelId, CryptoVec};
+use russh::ChannelId;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tracing::debug;
 
-async fn send_stderr(channel: ChannelId, handle: &Handle, msg: &str) {
-    let mut stderr = CryptoVec::from_slice(msg.as_bytes());
-    stderr.push(b'\r');
-    stderr.push(b'\n');
-    handle.extended_data(channel, 1, stderr).await.unwrap();
+/// Pattern matched against the whole command line sent by the client.
+///
+/// Pattern has to match the entire command line, not only a part of it.
+#[derive(Clone, Debug)]
+pub struct CommandPattern {
+    regex: Regex,
 }
 
-async fn send_stdout(channel: ChannelId, handle: &Handle, msg: &str) {
-    let mut stdout = CryptoVec::from_slice(msg.as_bytes());
-    stdout.push(b'\r');
-    stdout.push(b'\n');
-    handle.data(channel, stdout).await.unwrap();
+impl CommandPattern {
+    /// Create a shell-like pattern where `*` matches any sequence of characters
+    /// and `?` matches a single character.
+    ///
+    /// # Example
+    /// ```
+    /// use ssh_test_server::CommandPattern;
+    /// let pattern = CommandPattern::glob("systemctl status *");
+    ///
+    /// assert!(pattern.is_match("systemctl status nginx"));
+    /// assert!(!pattern.is_match("systemctl restart nginx"));
+    /// ```
+    pub fn glob(pattern: &str) -> Self {
+        let mut regex = String::new();
+        for c in pattern.chars() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        Self::regex(&regex).expect("escaped glob is a valid regex")
+    }
+
+    /// Create a pattern from a regular expression.
+    ///
+    /// # Example
+    /// ```
+    /// use ssh_test_server::CommandPattern;
+    /// let pattern = CommandPattern::regex(r"systemctl (start|stop) \w+").unwrap();
+    ///
+    /// assert!(pattern.is_match("systemctl stop nginx"));
+    /// assert!(!pattern.is_match("sudo systemctl stop nginx"));
+    /// ```
+    pub fn regex(pattern: &str) -> Result<Self> {
+        let regex = Regex::new(&format!("^(?s:{pattern})$"))
+            .map_err(|e| anyhow!("Invalid command pattern {pattern}: {e}"))?;
+        Ok(Self { regex })
+    }
+
+    /// Return true if the command line matches the pattern.
+    pub fn is_match(&self, command_line: &str) -> bool {
+        self.regex.is_match(command_line)
+    }
+}
+
+/// How a registered program is selected.
+enum Matcher {
+    /// Program name, the first word of the command line.
+    Name(String),
+    /// Pattern matched against the whole command line.
+    Pattern(CommandPattern),
+}
+
+/// Custom programs registered in the builder.
+#[derive(Default)]
+pub(crate) struct Programs {
+    /// Programs in the order of registration, first match wins.
+    registered: Vec<(Matcher, Box<dyn SshStreamingProgram>)>,
+    pub fallback: Option<Box<dyn SshStreamingProgram>>,
+    pub expectations: Expectations,
+}
+
+impl Programs {
+    /// Register program by name. Program registered earlier under the same
+    /// name is replaced and keeps its position.
+    pub fn add_named(&mut self, name: &str, program: Box<dyn SshStreamingProgram>) {
+        let existing = self
+            .registered
+            .iter_mut()
+            .find(|(matcher, _)| matches!(matcher, Matcher::Name(n) if n == name));
+        match existing {
+            Some((_, p)) => *p = program,
+            None => self
+                .registered
+                .push((Matcher::Name(name.to_string()), program)),
+        }
+    }
+
+    pub fn add_pattern(&mut self, pattern: CommandPattern, program: Box<dyn SshStreamingProgram>) {
+        self.registered.push((Matcher::Pattern(pattern), program));
+    }
+
+    /// Find the first program registered for the name or matching the command line.
+    pub fn find(&self, program: &str, command_line: &str) -> Option<&dyn SshStreamingProgram> {
+        self.registered
+            .iter()
+            .find(|(matcher, _)| match matcher {
+                Matcher::Name(name) => name == program,
+                Matcher::Pattern(pattern) => pattern.is_match(command_line),
+            })
+            .map(|(_, p)| p.as_ref())
+    }
+}
+
+/// Adapter of synchronous handler functions.
+pub(crate) struct SyncProgram(pub Box<SshExecuteHandler>);
+
+#[async_trait]
+impl SshProgram for SyncProgram {
+    async fn run(
+        &self,
+        context: &SshExecuteContext<'_>,
+        program: &str,
+        args: &[&str],
+    ) -> SshExecuteResult {
+        (self.0)(context, program, args)
+    }
+}
+
+/// Adapter of synchronous handler functions which receive raw command line.
+pub(crate) struct RawProgram(pub Box<SshRawExecuteHandler>);
+
+#[async_trait]
+impl SshProgram for RawProgram {
+    async fn run(
+        &self,
+        context: &SshExecuteContext<'_>,
+        _program: &str,
+        _args: &[&str],
+    ) -> SshExecuteResult {
+        (self.0)(context, context.command)
+    }
+}
+
+/// Output of a program run in a pseudo terminal.
+///
+/// Translates `\n` to `\r\n` like the `onlcr` terminal mode does.
+/// Without a terminal bytes are passed through untouched.
+pub(crate) struct TerminalOutput<W> {
+    inner: W,
+    pty: bool,
+    /// Bytes of the pending `\r\n` already written.
+    newline_written: usize,
+}
+
+impl<W> TerminalOutput<W> {
+    pub fn new(inner: W, pty: bool) -> Self {
+        Self {
+            inner,
+            pty,
+            newline_written: 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for TerminalOutput<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            // Channel writers never complete empty writes.
+            return Poll::Ready(Ok(0));
+        }
+        if !self.pty {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        }
+
+        if buf.first() != Some(&b'\n') {
+            let end = buf.iter().position(|b| *b == b'\n').unwrap_or(buf.len());
+            return Pin::new(&mut self.inner).poll_write(cx, &buf[..end]);
+        }
+
+        while self.newline_written < 2 {
+            let newline = &b"\r\n"[self.newline_written..];
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, newline))?;
+            if n == 0 {
+                return Poll::Ready(Ok(0));
+            }
+            self.newline_written += n;
+        }
+        self.newline_written = 0;
+        Poll::Ready(Ok(1))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+async fn write_line(output: &mut (dyn AsyncWrite + Send + Unpin + '_), msg: &str) {
+    let _ = output.write_all(msg.as_bytes()).await;
+    let _ = output.write_all(b"\n").await;
+}
+
+/// State of the connection needed to run commands.
+pub(crate) struct CommandEnv {
+    pub connection_id: usize,
+    pub user: String,
+    pub users: UsersMap,
+    pub programs: Arc<Programs>,
+    pub audit: Audit,
+    pub fs: MemoryFs,
 }
 
 pub async fn execute_command(
     command: Vec<u8>,
     channel: ChannelId,
     handle: &Handle,
-    session_user: &str,
-    users: &UsersMap,
-    programs: &ProgramsMap,
+    io: &mut SshExecuteIo<'_>,
+    env: &CommandEnv,
+    shell: bool,
 ) {
+    let CommandEnv {
+        connection_id,
+        user: session_user,
+        users,
+        programs,
+        audit,
+        fs,
+    } = env;
     let cmd = String::from_utf8_lossy(&command);
     let mut cmdline = cmd.to_string();
     let mut parse = cmdline_words_parser::parse_posix(&mut cmdline);
@@ -37,32 +253,29 @@ pub async fn execute_command(
 
     debug!("command: {cmd}, program {program} args: {args:?}");
 
-    if let Some(handler) = programs.get(program) {
-        let context = SshExecuteContext {
-            users,
-            current_user: session_user,
-        };
+    let context = SshExecuteContext {
+        users,
+        current_user: session_user,
+        command: &command,
+        command_line: &cmd,
+    };
 
+    let record = audit.command_started(*connection_id, session_user, &cmd, shell);
+    let expected = programs.expectations.record(session_user, &cmd);
+    let status_code = if let Some(handler) = expected {
         let r = handler(&context, program, &args);
-
-        if !r.stderr.is_empty() {
-            send_stderr(channel, handle, &r.stderr).await;
-        }
-        if !r.stdout.is_empty() {
-            send_stdout(channel, handle, &r.stdout).await;
-        }
-
-        handle
-            .exit_status_request(channel, r.status_code)
-            .await
-            .unwrap();
+        let _ = io.stderr().write_all(&r.stderr).await;
+        let _ = io.stdout().write_all(&r.stdout).await;
+        r.status_code
+    } else if let Some(handler) = programs.find(program, &cmd) {
+        handler.run_with_io(&context, program, &args, io).await
     } else if program == "echo" {
         let mut stdout = String::new();
         for a in args {
             stdout.push_str(a);
         }
-        send_stdout(channel, handle, &stdout).await;
-        handle.exit_status_request(channel, 0).await.unwrap();
+        write_line(io.stdout(), &stdout).await;
+        0
     } else if program == "change_password" {
         match args.first() {
             Some(new_password) => {
@@ -71,25 +284,37 @@ pub async fn execute_command(
                     let user = users.get_mut(session_user).unwrap();
                     user.set_password(new_password);
                 }
-                send_stdout(channel, handle, "password changed").await;
-                handle.exit_status_request(channel, 0).await.unwrap();
+                write_line(io.stdout(), "password changed").await;
+                0
             }
             None => {
-                send_stdout(
-                    channel,
-                    handle,
+                write_line(
+                    io.stdout(),
                     "no password Usage: change_password <new_password>",
                 )
                 .await;
-                handle.exit_status_request(channel, 1).await.unwrap();
+                1
             }
         }
     } else if program == "exit" {
-        handle.exit_status_request(channel, 0).await.unwrap();
-        handle.close(channel).await.unwrap();
+        0
+    } else if let Some(scp) = Scp::parse(&command).filter(|_| !shell) {
+        scp.run(io, fs, *connection_id).await
+    } else if let Some(fallback) = &programs.fallback {
+        fallback.run_with_io(&context, program, &args, io).await
     } else {
         let msg = format!("{program}: command not found");
-        send_stderr(channel, handle, &msg).await;
-        handle.exit_status_request(channel, 127).await.unwrap();
+        write_line(io.stderr(), &msg).await;
+        127
+    };
+
+    io.flush().await;
+    audit.command_finished(record, status_code);
+    handle
+        .exit_status_request(channel, status_code)
+        .await
+        .unwrap();
+    if program == "exit" {
+        handle.close(channel).await.unwrap();
     }
 }
@@ -0,0 +1,208 @@
+use crate::session::SshConnection;
+use russh::server::{self, Config, Handle};
+use russh::Disconnect;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+/// Give up waiting for clients which don't close connections after the disconnect.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Connection {
+    task: JoinHandle<()>,
+    /// Handle of the session, available after the identification exchange.
+    handle: Option<Handle>,
+    /// Closes the stream of the session. Aborting the task is not enough,
+    /// russh runs the session in its own task.
+    close: Arc<CloseSignal>,
+}
+
+/// Signal which makes [ClosableStream] behave like a closed connection.
+#[derive(Default)]
+struct CloseSignal {
+    closed: AtomicBool,
+    /// Wakers of pending read and write.
+    wakers: Mutex<[Option<Waker>; 2]>,
+}
+
+impl CloseSignal {
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        for waker in self.wakers.lock().unwrap().iter_mut() {
+            if let Some(waker) = waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Return true when closed, otherwise register the waker of the operation.
+    fn is_closed(&self, operation: usize, cx: &Context<'_>) -> bool {
+        let mut wakers = self.wakers.lock().unwrap();
+        if self.closed.load(Ordering::SeqCst) {
+            return true;
+        }
+        wakers[operation] = Some(cx.waker().clone());
+        false
+    }
+}
+
+/// Stream of a session which can be closed by the server.
+struct ClosableStream<S> {
+    inner: S,
+    close: Arc<CloseSignal>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ClosableStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.close.is_closed(0, cx) {
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ClosableStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.close.is_closed(1, cx) {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Tasks of open connections.
+#[derive(Clone, Default)]
+pub(crate) struct Connections {
+    inner: Arc<Mutex<HashMap<usize, Connection>>>,
+}
+
+impl Connections {
+    /// Run ssh session over the stream in a new task.
+    pub fn serve<S>(&self, id: usize, config: Arc<Config>, stream: S, handler: SshConnection)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        // Task removes itself, keep the map locked until it's registered.
+        let mut inner = self.inner.lock().unwrap();
+        let connections = self.clone();
+        let close = Arc::new(CloseSignal::default());
+        let stream = ClosableStream {
+            inner: stream,
+            close: close.clone(),
+        };
+        let task = tokio::spawn(async move {
+            match server::run_stream(config, stream, handler).await {
+                Ok(session) => {
+                    if let Some(c) = connections.inner.lock().unwrap().get_mut(&id) {
+                        c.handle = Some(session.handle());
+                    }
+                    if let Err(e) = session.await {
+                        debug!(connection_id = id, "session failed: {e}");
+                    }
+                }
+                Err(e) => debug!(connection_id = id, "session not started: {e}"),
+            }
+            connections.inner.lock().unwrap().remove(&id);
+        });
+        inner.insert(
+            id,
+            Connection {
+                task,
+                handle: None,
+                close,
+            },
+        );
+    }
+
+    /// Disconnect all clients and wait until their sessions finish.
+    /// Sessions of clients which ignore the disconnect are aborted after a timeout.
+    pub async fn shutdown(&self) {
+        let mut connections: Vec<_> = self.inner.lock().unwrap().drain().collect();
+        for (id, connection) in &connections {
+            match &connection.handle {
+                Some(handle) => {
+                    let description = "Server shutdown".to_string();
+                    let result = handle
+                        .disconnect(Disconnect::ByApplication, description, String::new())
+                        .await;
+                    debug!(connection_id = id, "disconnect {result:?}");
+                }
+                None => connection.close.close(),
+            }
+        }
+
+        let wait = async {
+            for (_, connection) in &mut connections {
+                let _ = (&mut connection.task).await;
+            }
+        };
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, wait).await.is_err() {
+            debug!("clients didn't close connections after disconnect, closing them");
+            for (_, connection) in connections {
+                if !connection.task.is_finished() {
+                    connection.close.close();
+                    let _ = connection.task.await;
+                }
+            }
+        }
+    }
+
+    /// Disconnect all clients without waiting. Sessions which don't finish
+    /// after the disconnect are closed in the background.
+    pub fn abort(&self) {
+        let connections: Vec<_> = self.inner.lock().unwrap().drain().collect();
+        let runtime = tokio::runtime::Handle::try_current();
+        for (_, connection) in connections {
+            let (Some(handle), Ok(runtime)) = (connection.handle, &runtime) else {
+                connection.close.close();
+                continue;
+            };
+            let Connection {
+                mut task, close, ..
+            } = connection;
+            runtime.spawn(async move {
+                let description = "Server shutdown".to_string();
+                let _ = handle
+                    .disconnect(Disconnect::ByApplication, description, String::new())
+                    .await;
+                if tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut task)
+                    .await
+                    .is_err()
+                {
+                    close.close();
+                }
+            });
+        }
+    }
+}
+
+impl fmt::Debug for Connections {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_set().entries(inner.keys()).finish()
+    }
+}
@@ -0,0 +1,53 @@
+use std::{fmt, io};
+
+/// Errors of the ssh server which tests may want to handle.
+///
+/// Functions return [anyhow::Error], use [anyhow::Error::downcast_ref] to get it.
+///
+/// # Example
+///
+/// ```
+/// use ssh_test_server::{SshServerBuilder, SshServerError};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let first = SshServerBuilder::default().run().await.unwrap();
+/// let error = SshServerBuilder::default()
+///     .port(first.port())
+///     .run()
+///     .await
+///     .unwrap_err();
+///
+/// assert!(matches!(
+///     error.downcast_ref(),
+///     Some(SshServerError::AddrInUse { .. })
+/// ));
+/// # }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SshServerError {
+    /// Address is already used by another socket.
+    AddrInUse {
+        /// Address with the port.
+        addr: String,
+        /// Error returned by the operating system.
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for SshServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddrInUse { addr, .. } => write!(f, "Address {addr} is already in use"),
+        }
+    }
+}
+
+impl std::error::Error for SshServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::AddrInUse { source, .. } => Some(source),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use russh::MethodSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Activity of the ssh server reported by [SshServer::subscribe](crate::SshServer::subscribe).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// Client connected.
+    Connected {
+        /// Id of the connection.
+        connection_id: usize,
+        /// Address of the client.
+        peer_addr: Option<SocketAddr>,
+    },
+    /// Client tried to authenticate.
+    AuthAttempted {
+        /// Id of the connection.
+        connection_id: usize,
+        /// Login sent by the client.
+        user: String,
+        /// Authentication method.
+        method: MethodSet,
+    },
+    /// User authenticated.
+    AuthSucceeded {
+        /// Id of the connection.
+        connection_id: usize,
+        /// Login of the user.
+        user: String,
+        /// Method of the last authentication factor.
+        method: MethodSet,
+    },
+    /// Authentication attempt didn't authenticate the user.
+    AuthFailed {
+        /// Id of the connection.
+        connection_id: usize,
+        /// Login sent by the client.
+        user: String,
+        /// Authentication method.
+        method: MethodSet,
+        /// True when the factor was accepted but the next authentication step
+        /// is required. The client isn't told about it, see
+        /// [AuthOutcome::StepPassed](crate::AuthOutcome::StepPassed).
+        step_passed: bool,
+    },
+    /// Client opened a channel.
+    ChannelOpened {
+        /// Id of the connection.
+        connection_id: usize,
+        /// Channel type, for example `session` or `direct-tcpip`.
+        channel_type: String,
+    },
+    /// Client requested a pseudo terminal.
+    PtyRequested {
+        /// Id of the connection.
+        connection_id: usize,
+        /// Terminal name, for example `xterm`.
+        term: String,
+        /// Terminal width in characters.
+        col_width: u32,
+        /// Terminal height in rows.
+        row_height: u32,
+    },
+    /// Command started in an exec channel or an interactive shell.
+    CommandStarted {
+        /// Id of the connection.
+        connection_id: usize,
+        /// Login of the user.
+        user: String,
+        /// Command line.
+        command: String,
+    },
+    /// Command finished.
+    CommandFinished {
+        /// Id of the connection.
+        connection_id: usize,
+        /// Login of the user.
+        user: String,
+        /// Command line.
+        command: String,
+        /// Exit code of the command.
+        exit_code: u32,
+    },
+    /// Connection closed.
+    Disconnected {
+        /// Id of the connection.
+        connection_id: usize,
+    },
+    /// Server stopped by [SshServer::stop](crate::SshServer::stop)
+    /// or [SshServer::shutdown](crate::SshServer::shutdown).
+    Stopped,
+    /// Server accepts connections again after
+    /// [SshServer::restart](crate::SshServer::restart).
+    Restarted {
+        /// Time since the server was stopped.
+        downtime: Duration,
+    },
+}
@@ -0,0 +1,170 @@
+use crate::{CommandPattern, SshExecuteContext, SshExecuteHandler, SshExecuteResult};
+use anyhow::{bail, Result};
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Command expected to be run by a client.
+///
+/// Created by [SshServer::expect_command](crate::SshServer::expect_command)
+/// or [SshServerBuilder::expect_command](crate::SshServerBuilder::expect_command).
+/// By default the command is expected exactly once and it's executed
+/// like any other command.
+pub struct Expectation {
+    expectations: Expectations,
+    index: usize,
+}
+
+impl Expectation {
+    /// Expect the command to be run exactly `times` times.
+    pub fn times(self, times: usize) -> Self {
+        self.expectations.inner.lock().unwrap().expected[self.index].times = times;
+        self
+    }
+
+    /// Respond to the expected command with the handler instead of
+    /// registered programs and built-in commands.
+    pub fn returning<F>(self, handler: F) -> Self
+    where
+        F: Fn(&SshExecuteContext, &str, &[&str]) -> SshExecuteResult + Send + Sync + 'static,
+    {
+        self.expectations.inner.lock().unwrap().expected[self.index].handler =
+            Some(Arc::new(handler));
+        self
+    }
+}
+
+struct Expected {
+    glob: String,
+    pattern: CommandPattern,
+    times: usize,
+    handler: Option<Arc<SshExecuteHandler>>,
+}
+
+/// Command run by a client.
+struct Recorded {
+    user: String,
+    command_line: String,
+    expectation: Option<usize>,
+}
+
+#[derive(Default)]
+struct ExpectationsInner {
+    expected: Vec<Expected>,
+    recorded: Vec<Recorded>,
+}
+
+/// Expected and recorded commands of all connections.
+#[derive(Clone, Default)]
+pub(crate) struct Expectations {
+    inner: Arc<Mutex<ExpectationsInner>>,
+}
+
+impl Expectations {
+    pub fn add(&self, glob: &str) -> Expectation {
+        let mut inner = self.inner.lock().unwrap();
+        inner.expected.push(Expected {
+            glob: glob.to_string(),
+            pattern: CommandPattern::glob(glob),
+            times: 1,
+            handler: None,
+        });
+        Expectation {
+            expectations: self.clone(),
+            index: inner.expected.len() - 1,
+        }
+    }
+
+    /// Record the command. Returns handler of the matching expectation.
+    pub fn record(&self, user: &str, command_line: &str) -> Option<Arc<SshExecuteHandler>> {
+        let mut inner = self.inner.lock().unwrap();
+        let calls = |inner: &ExpectationsInner, index| {
+            inner
+                .recorded
+                .iter()
+                .filter(|r| r.expectation == Some(index))
+                .count()
+        };
+
+        // Prefer expectations which still wait for calls.
+        let matching: Vec<_> = (0..inner.expected.len())
+            .filter(|i| inner.expected[*i].pattern.is_match(command_line))
+            .collect();
+        let expectation = matching
+            .iter()
+            .find(|i| calls(&inner, **i) < inner.expected[**i].times)
+            .or(matching.first())
+            .copied();
+
+        inner.recorded.push(Recorded {
+            user: user.to_string(),
+            command_line: command_line.to_string(),
+            expectation,
+        });
+        expectation.and_then(|i| inner.expected[i].handler.clone())
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        let inner = self.inner.lock().unwrap();
+        let mut calls = vec![0; inner.expected.len()];
+        let mut errors = String::new();
+
+        for recorded in &inner.recorded {
+            let Recorded {
+                user,
+                command_line,
+                expectation,
+            } = recorded;
+            let Some(index) = *expectation else {
+                let _ = writeln!(
+                    errors,
+                    "  unexpected command `{command_line}` run by {user}"
+                );
+                continue;
+            };
+
+            let expected = &inner.expected[index];
+            calls[index] += 1;
+            if calls[index] > expected.times {
+                let _ = writeln!(
+                    errors,
+                    "  unexpected command `{command_line}` run by {user}: `{}` expected {} times",
+                    expected.glob, expected.times
+                );
+                continue;
+            }
+
+            let pending = (0..index).find(|i| calls[*i] < inner.expected[*i].times);
+            if let Some(pending) = pending {
+                let _ = writeln!(
+                    errors,
+                    "  out of order command `{command_line}` run by {user}: expected after `{}`",
+                    inner.expected[pending].glob
+                );
+            }
+        }
+
+        for (expected, calls) in inner.expected.iter().zip(calls) {
+            if calls < expected.times {
+                let _ = writeln!(
+                    errors,
+                    "  missing command `{}`: expected {} times, run {calls} times",
+                    expected.glob, expected.times
+                );
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("Ssh command expectations not met:\n{errors}");
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Expectations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_list()
+            .entries(inner.expected.iter().map(|e| &e.glob))
+            .finish()
+    }
+}
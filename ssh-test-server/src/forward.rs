@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use russh::server::{Handle, Msg};
+use russh::{Channel, ChannelStream};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tracing::debug;
+
+/// Bidirectional stream of a forwarded connection.
+pub type TunnelStream = ChannelStream<Msg>;
+
+/// Function signature for virtual tcp endpoints.
+pub type TcpEndpointHandler =
+    dyn Fn(TunnelStream) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync;
+
+/// Connection opened by a client through local port forwarding (`ssh -L`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalForward {
+    /// Id of the ssh connection.
+    pub connection_id: usize,
+    /// Login of the user who opened the connection.
+    pub user: String,
+    /// Target host requested by the client.
+    pub host: String,
+    /// Target port requested by the client.
+    pub port: u32,
+    /// Address of the client's side of the tunnel.
+    pub originator_address: String,
+    /// Port of the client's side of the tunnel.
+    pub originator_port: u32,
+    /// True when the connection was served by a virtual endpoint or a tcp connection.
+    pub accepted: bool,
+}
+
+/// Port forwarding requested by a client with remote port forwarding (`ssh -R`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteForward {
+    /// Id of the ssh connection.
+    pub connection_id: usize,
+    /// Login of the user who requested the forwarding.
+    pub user: String,
+    /// Address requested by the client.
+    pub address: String,
+    /// Port requested by the client or allocated by the server when client requested port 0.
+    pub port: u32,
+}
+
+/// First port allocated for remote forwards requested with port 0.
+const FIRST_ALLOCATED_PORT: u32 = 32768;
+
+/// Active remote forwards of all connections.
+#[derive(Clone, Default)]
+pub(crate) struct RemoteForwards {
+    inner: Arc<Mutex<RemoteForwardsInner>>,
+}
+
+#[derive(Default)]
+struct RemoteForwardsInner {
+    forwards: Vec<(RemoteForward, Handle)>,
+    next_port: u32,
+}
+
+impl RemoteForwards {
+    /// Register forwarding. Returns the port or [None] when it's already forwarded.
+    pub fn add(
+        &self,
+        connection_id: usize,
+        user: &str,
+        address: &str,
+        port: u32,
+        handle: Handle,
+    ) -> Option<u32> {
+        let mut inner = self.inner.lock().unwrap();
+        let used = |inner: &RemoteForwardsInner, port| {
+            inner
+                .forwards
+                .iter()
+                .any(|(f, _)| f.address == address && f.port == port)
+        };
+
+        let port = if port == 0 {
+            let mut port = inner.next_port.max(FIRST_ALLOCATED_PORT);
+            while used(&inner, port) {
+                port += 1;
+            }
+            inner.next_port = port + 1;
+            port
+        } else if used(&inner, port) {
+            return None;
+        } else {
+            port
+        };
+
+        let forward = RemoteForward {
+            connection_id,
+            user: user.to_string(),
+            address: address.to_string(),
+            port,
+        };
+        inner.forwards.push((forward, handle));
+        Some(port)
+    }
+
+    /// Remove forwarding. Returns false when it wasn't registered by the connection.
+    pub fn cancel(&self, connection_id: usize, address: &str, port: u32) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.forwards.len();
+        inner.forwards.retain(|(f, _)| {
+            !(f.connection_id == connection_id && f.address == address && f.port == port)
+        });
+        inner.forwards.len() != before
+    }
+
+    /// Remove all forwards of a closed connection.
+    pub fn remove_connection(&self, connection_id: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .forwards
+            .retain(|(f, _)| f.connection_id != connection_id);
+    }
+
+    pub fn list(&self) -> Vec<RemoteForward> {
+        let inner = self.inner.lock().unwrap();
+        inner.forwards.iter().map(|(f, _)| f.clone()).collect()
+    }
+
+    /// Open forwarded-tcpip channel to the client which requested the forwarding.
+    pub async fn open(
+        &self,
+        address: &str,
+        port: u32,
+        originator_address: &str,
+        originator_port: u32,
+    ) -> Result<TunnelStream> {
+        let handle = self
+            .inner
+            .lock()
+            .unwrap()
+            .forwards
+            .iter()
+            .rev()
+            .find(|(f, _)| f.address == address && f.port == port)
+            .map(|(_, handle)| handle.clone())
+            .ok_or_else(|| anyhow!("No remote forwarding of {address}:{port}"))?;
+
+        let channel = handle
+            .channel_open_forwarded_tcpip(address, port, originator_address, originator_port)
+            .await?;
+        Ok(channel.into_stream())
+    }
+}
+
+impl fmt::Debug for RemoteForwards {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.list()).finish()
+    }
+}
+
+/// Port forwarding settings and history shared by all connections.
+#[derive(Default)]
+pub(crate) struct Forwarding {
+    pub endpoints: HashMap<(String, u32), Arc<TcpEndpointHandler>>,
+    pub tcp_fallback: bool,
+    pub local_forwards: Arc<Mutex<Vec<LocalForward>>>,
+    pub remote_forwards: RemoteForwards,
+}
+
+impl Forwarding {
+    /// Serve direct-tcpip channel in a task added to `tasks`.
+    /// Returns false when the channel is rejected.
+    pub async fn open_local(
+        &self,
+        channel: Channel<Msg>,
+        handle: Handle,
+        mut forward: LocalForward,
+        tasks: &mut JoinSet<()>,
+    ) -> bool {
+        let target = (forward.host.clone(), forward.port);
+        let id = channel.id();
+
+        if let Some(endpoint) = self.endpoints.get(&target) {
+            let task = endpoint(channel.into_stream());
+            tasks.spawn(async move {
+                task.await;
+                let _ = handle.close(id).await;
+            });
+            forward.accepted = true;
+        } else if let (true, Ok(port)) = (self.tcp_fallback, u16::try_from(forward.port)) {
+            let host = forward.host.as_str();
+            match TcpStream::connect((host, port)).await {
+                Ok(mut socket) => {
+                    tasks.spawn(async move {
+                        let mut stream = channel.into_stream();
+                        let result = tokio::io::copy_bidirectional(&mut stream, &mut socket).await;
+                        debug!("tunnel to {target:?} finished {result:?}");
+                        let _ = handle.close(id).await;
+                    });
+                    forward.accepted = true;
+                }
+                Err(e) => debug!("tunnel to {target:?} failed: {e}"),
+            }
+        }
+
+        let accepted = forward.accepted;
+        self.local_forwards.lock().unwrap().push(forward);
+        accepted
+    }
+}
@@ -46,19 +46,56 @@
 //! ```
 //!
 #![warn(missing_docs)]
+use async_trait::async_trait;
+use listener::Acceptor;
 use russh_keys::key::PublicKey;
 use russh_keys::PublicKeyBase64;
 use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::sync::broadcast;
 use tokio::task::JoinHandle;
 
+mod algorithms;
+mod audit;
+mod auth;
+mod blocking;
 mod builder;
+#[cfg(feature = "client")]
+pub mod client;
 mod command;
+mod connections;
+mod error;
+mod events;
+mod expect;
+mod forward;
+mod listener;
+mod scp;
 mod session;
+mod sftp;
 mod user;
+mod vfs;
 
+pub use algorithms::NegotiatedAlgorithms;
+pub use audit::{AuditLog, AuthOutcome, AuthRecord, CommandRecord, ConnectionRecord};
+pub use auth::KeyboardInteractive;
+pub use blocking::BlockingSshServer;
 pub use builder::SshServerBuilder;
+pub use command::CommandPattern;
+pub use error::SshServerError;
+pub use events::ServerEvent;
+pub use expect::Expectation;
+pub use forward::{LocalForward, RemoteForward, TcpEndpointHandler, TunnelStream};
+pub use listener::Endpoint;
+pub use russh::MethodSet;
 pub use user::User;
+pub use vfs::{FileType, MemoryFs, Metadata};
+
+/// Buffer size of each direction of in-memory connections.
+const IN_MEMORY_BUFFER_SIZE: usize = 64 * 1024;
 
 /// Users required in ssh server context.
 /// Key of the hash map is a user login.
@@ -68,6 +105,199 @@ pub type UsersMap = Arc<Mutex<HashMap<String, User>>>;
 pub type SshExecuteHandler =
     dyn Fn(&SshExecuteContext, &str, &[&str]) -> SshExecuteResult + Sync + Send;
 
+/// Function signature for custom commands which parse the command line themselves.
+///
+/// Handler receives raw bytes of the whole command line sent by the client.
+pub type SshRawExecuteHandler = dyn Fn(&SshExecuteContext, &[u8]) -> SshExecuteResult + Sync + Send;
+
+/// Custom command/program which can await, for example a channel from the test
+/// or a timer, without blocking the server.
+///
+/// Synchronous [SshExecuteHandler] functions are adapted to this trait
+/// by [SshServerBuilder::add_program].
+/// See [SshStreamingProgram] for programs which stream output or read
+/// data sent by the client.
+///
+/// # Example
+///
+/// ```
+/// use async_trait::async_trait;
+/// use ssh_test_server::{SshExecuteContext, SshExecuteResult, SshProgram, SshServerBuilder};
+/// use std::time::Duration;
+///
+/// struct Sleep;
+///
+/// #[async_trait]
+/// impl SshProgram for Sleep {
+///     async fn run(
+///         &self,
+///         _context: &SshExecuteContext<'_>,
+///         _program: &str,
+///         args: &[&str],
+///     ) -> SshExecuteResult {
+///         let Some(Ok(secs)) = args.first().map(|a| a.parse()) else {
+///             return SshExecuteResult::stderr(1, "sleep: missing operand\n");
+///         };
+///         tokio::time::sleep(Duration::from_secs(secs)).await;
+///         SshExecuteResult::stdout(0, "")
+///     }
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let _ssh = SshServerBuilder::default()
+///     .add_async_program("sleep", Sleep)
+///     .run()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+///
+/// [SshProgram::run] has to be implemented:
+///
+/// ```compile_fail,E0046
+/// use async_trait::async_trait;
+/// use ssh_test_server::SshProgram;
+///
+/// struct Noop;
+///
+/// #[async_trait]
+/// impl SshProgram for Noop {}
+/// ```
+#[async_trait]
+pub trait SshProgram: Send + Sync {
+    /// Run the program with arguments and return its whole output at once.
+    async fn run(
+        &self,
+        context: &SshExecuteContext<'_>,
+        program: &str,
+        args: &[&str],
+    ) -> SshExecuteResult;
+}
+
+/// Custom command/program with access to the channel's standard streams,
+/// for programs which stream output or read data sent by the client.
+///
+/// Every [SshProgram] is a streaming program which writes its whole
+/// result when it finishes.
+///
+/// # Example
+///
+/// ```
+/// use async_trait::async_trait;
+/// use ssh_test_server::{
+///     SshExecuteContext, SshExecuteIo, SshServerBuilder, SshStreamingProgram,
+/// };
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+///
+/// struct Cat;
+///
+/// #[async_trait]
+/// impl SshStreamingProgram for Cat {
+///     async fn run_with_io(
+///         &self,
+///         _context: &SshExecuteContext<'_>,
+///         _program: &str,
+///         _args: &[&str],
+///         io: &mut SshExecuteIo<'_>,
+///     ) -> u32 {
+///         let mut buf = [0; 1024];
+///         loop {
+///             match io.stdin().read(&mut buf).await {
+///                 Ok(0) => return 0,
+///                 Ok(n) => {
+///                     if io.stdout().write_all(&buf[..n]).await.is_err() {
+///                         return 1;
+///                     }
+///                 }
+///                 Err(_) => return 1,
+///             }
+///         }
+///     }
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let _ssh = SshServerBuilder::default()
+///     .add_async_program("cat", Cat)
+///     .run()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[async_trait]
+pub trait SshStreamingProgram: Send + Sync {
+    /// Run the program with arguments. Returns exit code.
+    async fn run_with_io(
+        &self,
+        context: &SshExecuteContext<'_>,
+        program: &str,
+        args: &[&str],
+        io: &mut SshExecuteIo<'_>,
+    ) -> u32;
+}
+
+#[async_trait]
+impl<P: SshProgram> SshStreamingProgram for P {
+    async fn run_with_io(
+        &self,
+        context: &SshExecuteContext<'_>,
+        program: &str,
+        args: &[&str],
+        io: &mut SshExecuteIo<'_>,
+    ) -> u32 {
+        let r = self.run(context, program, args).await;
+        let _ = io.stderr().write_all(&r.stderr).await;
+        let _ = io.stdout().write_all(&r.stdout).await;
+        r.status_code
+    }
+}
+
+/// Standard streams of a running custom program.
+///
+/// In exec channels stdin receives data sent by the client and ends when
+/// the client sends EOF. In interactive shells stdin is always empty.
+pub struct SshExecuteIo<'a> {
+    stdin: Box<dyn AsyncRead + Send + Unpin + 'a>,
+    stdout: Box<dyn AsyncWrite + Send + Unpin + 'a>,
+    stderr: Box<dyn AsyncWrite + Send + Unpin + 'a>,
+}
+
+impl<'a> SshExecuteIo<'a> {
+    pub(crate) fn new(
+        stdin: impl AsyncRead + Send + Unpin + 'a,
+        stdout: impl AsyncWrite + Send + Unpin + 'a,
+        stderr: impl AsyncWrite + Send + Unpin + 'a,
+    ) -> Self {
+        Self {
+            stdin: Box::new(stdin),
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+        }
+    }
+
+    /// Data sent by the client.
+    pub fn stdin(&mut self) -> &mut (dyn AsyncRead + Send + Unpin + 'a) {
+        &mut *self.stdin
+    }
+
+    /// Standard output sent to the client.
+    pub fn stdout(&mut self) -> &mut (dyn AsyncWrite + Send + Unpin + 'a) {
+        &mut *self.stdout
+    }
+
+    /// Standard error sent to the client.
+    pub fn stderr(&mut self) -> &mut (dyn AsyncWrite + Send + Unpin + 'a) {
+        &mut *self.stderr
+    }
+
+    /// Flush both output streams.
+    pub(crate) async fn flush(&mut self) {
+        let _ = self.stdout.flush().await;
+        let _ = self.stderr.flush().await;
+    }
+}
+
 /// Context of ssh server passed to every custom function.
 ///
 /// For example, it's allows to implement program that modifies
@@ -77,6 +307,13 @@ pub struct SshExecuteContext<'a> {
     pub users: &'a UsersMap,
     /// Current user's login.
     pub current_user: &'a str,
+    /// Raw bytes of the command sent by the client.
+    pub command: &'a [u8],
+    /// Whole command line before splitting into program and arguments.
+    ///
+    /// Invalid UTF-8 sequences are replaced, see [SshExecuteContext::command]
+    /// for the original bytes.
+    pub command_line: &'a str,
 }
 
 impl<'a> SshExecuteContext<'a> {
@@ -92,11 +329,14 @@ impl<'a> SshExecuteContext<'a> {
 }
 
 /// Response that have to be returned by custom command handler.
+///
+/// Output is sent to the client byte for byte. Newlines are translated
+/// to `\r\n` only when the client allocated a pseudo terminal.
 pub struct SshExecuteResult {
     /// Standard output.
-    pub stdout: String,
+    pub stdout: Vec<u8>,
     /// Standard error.
-    pub stderr: String,
+    pub stderr: Vec<u8>,
     /// Program exit code. Usually 0 means success.
     pub status_code: u32,
 }
@@ -110,12 +350,12 @@ impl SshExecuteResult {
     /// let result = SshExecuteResult::stdout(0, "Password chained.");
     ///
     /// assert_eq!(result.status_code, 0);
-    /// assert_eq!(result.stdout, "Password chained.");
+    /// assert_eq!(result.stdout, b"Password chained.");
     /// ```
-    pub fn stdout(status_code: u32, stdout: impl Into<String>) -> Self {
+    pub fn stdout(status_code: u32, stdout: impl Into<Vec<u8>>) -> Self {
         Self {
             stdout: stdout.into(),
-            stderr: "".to_string(),
+            stderr: vec![],
             status_code,
         }
     }
@@ -128,11 +368,11 @@ impl SshExecuteResult {
     /// let result = SshExecuteResult::stderr(1, "Permission denied.");
     ///
     /// assert_eq!(result.status_code, 1);
-    /// assert_eq!(result.stderr, "Permission denied.");
+    /// assert_eq!(result.stderr, b"Permission denied.");
     /// ```
-    pub fn stderr(status_code: u32, stderr: impl Into<String>) -> Self {
+    pub fn stderr(status_code: u32, stderr: impl Into<Vec<u8>>) -> Self {
         Self {
-            stdout: "".to_string(),
+            stdout: vec![],
             stderr: stderr.into(),
             status_code,
         }
@@ -144,51 +384,499 @@ impl SshExecuteResult {
 /// When is dropped then ssh server stops.
 #[derive(Debug)]
 pub struct SshServer {
-    listener: JoinHandle<()>,
-    users: UsersMap,
+    listener: Mutex<Listener>,
+    acceptor: Arc<Acceptor>,
     port: u16,
     host: String,
-    server_public_key: PublicKey,
+    endpoints: Vec<Endpoint>,
+    server_public_keys: Vec<PublicKey>,
+}
+
+/// Accept loop of the server.
+#[derive(Debug, Default)]
+struct Listener {
+    /// Tasks accepting connections, one per endpoint, empty after shutdown.
+    tasks: Vec<JoinHandle<()>>,
+    /// Time when the server was stopped.
+    stopped_at: Option<Instant>,
 }
 
 impl SshServer {
-    /// IP or hostname of the ssh server.
+    /// IP or hostname of the first TCP endpoint of the ssh server.
+    ///
+    /// Empty when the server listens only on Unix sockets.
     pub fn host(&self) -> &str {
         &self.host
     }
 
-    /// Port number of the ssh server.
+    /// Port number of the first TCP endpoint of the ssh server.
+    ///
+    /// 0 when the server listens only on Unix sockets.
     pub fn port(&self) -> u16 {
         self.port
     }
 
-    /// Host and port pair.
+    /// Host and port pair of the first TCP endpoint.
     ///
     /// Format:
     /// ```text
     /// <host>:<port>
+    /// [<ipv6>]:<port>
     /// ```
     pub fn addr(&self) -> String {
-        format!("{}:{}", self.host(), self.port())
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host(), self.port())
+        } else {
+            format!("{}:{}", self.host(), self.port())
+        }
+    }
+
+    /// All endpoints where the server accepts connections, with ports
+    /// assigned by the system.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{Endpoint, SshServerBuilder};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default()
+    ///     .listen("127.0.0.1:0")
+    ///     .listen("[::1]:0")
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let endpoints = ssh.endpoints();
+    /// assert_eq!(endpoints.len(), 2);
+    /// assert!(matches!(endpoints[1], Endpoint::Tcp(addr) if addr.is_ipv6()));
+    /// assert_eq!(endpoints[0].to_string(), ssh.addr());
+    /// # }
+    /// ```
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        self.endpoints.clone()
     }
 
     /// Ssh public key of the ssh server.
+    ///
+    /// When server has multiple host keys then the most preferred one is returned.
+    ///
+    /// Format:
+    /// ```text
+    /// <algorithm> <base64 key>
+    /// ```
     pub fn server_public_key(&self) -> String {
-        format!(
-            "{} {}",
-            self.server_public_key.name(),
-            self.server_public_key.public_key_base64()
-        )
+        openssh_public_key(&self.server_public_keys[0])
+    }
+
+    /// Ssh public keys of all host keys of the ssh server in preference order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// use russh_keys::key::{KeyPair, SignatureHash};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default()
+    ///     .host_key(KeyPair::generate_ed25519())
+    ///     .host_key(KeyPair::generate_rsa(2048, SignatureHash::SHA2_256).unwrap())
+    ///     .run()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let keys = ssh.server_public_keys();
+    /// assert!(keys[0].starts_with("ssh-ed25519 "));
+    /// assert!(keys[1].starts_with("ssh-rsa "));
+    /// # }
+    /// ```
+    pub fn server_public_keys(&self) -> Vec<String> {
+        self.server_public_keys
+            .iter()
+            .map(openssh_public_key)
+            .collect()
+    }
+
+    /// Algorithms negotiated with clients, ordered by connection id.
+    pub fn negotiated_algorithms(&self) -> Vec<NegotiatedAlgorithms> {
+        let mut negotiated: Vec<_> = self
+            .acceptor
+            .negotiated
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        negotiated.sort_by_key(|n| n.connection_id);
+        negotiated
     }
 
     /// Registered users in the ssh server.
     pub fn users(&self) -> UsersMap {
-        self.users.clone()
+        self.acceptor.users.clone()
+    }
+
+    /// Connections opened by clients with local port forwarding, in order.
+    ///
+    /// Rejected connections are recorded too.
+    pub fn local_forwards(&self) -> Vec<LocalForward> {
+        self.acceptor
+            .forwarding
+            .local_forwards
+            .lock()
+            .unwrap()
+            .clone()
+    }
+
+    /// Active remote port forwards requested by clients.
+    pub fn remote_forwards(&self) -> Vec<RemoteForward> {
+        self.acceptor.forwarding.remote_forwards.list()
+    }
+
+    /// Open `forwarded-tcpip` channel to the client which requested remote
+    /// forwarding of `address` and `port`, like a new connection accepted on
+    /// the forwarded port.
+    ///
+    /// Fails when there is no such forwarding or the client rejects the channel.
+    pub async fn open_remote_forward(
+        &self,
+        address: &str,
+        port: u32,
+        originator_address: &str,
+        originator_port: u32,
+    ) -> anyhow::Result<TunnelStream> {
+        self.acceptor
+            .forwarding
+            .remote_forwards
+            .open(address, port, originator_address, originator_port)
+            .await
+    }
+
+    /// Stop accepting connections, disconnect all clients and wait until
+    /// their sessions finish. Port of the server is released.
+    ///
+    /// Dropping the server disconnects clients too, but without waiting.
+    /// See [SshServer::stop] to keep the port reserved.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().run().await.unwrap();
+    /// ssh.shutdown().await;
+    ///
+    /// assert!(std::net::TcpStream::connect(ssh.addr()).is_err());
+    /// # }
+    /// ```
+    pub async fn shutdown(&self) {
+        self.stop().await;
+        let tasks = std::mem::take(&mut self.listener.lock().unwrap().tasks);
+        if !tasks.is_empty() {
+            for task in tasks {
+                task.abort();
+                let _ = task.await;
+            }
+            self.endpoints.iter().for_each(listener::unbind);
+        }
+    }
+
+    /// Bring the server down, for example to test reconnect logic of clients.
+    ///
+    /// All clients are disconnected and new connections are closed right
+    /// after they are accepted. Port, host keys, users and other state are
+    /// kept, so [SshServer::restart] brings the same server back up.
+    /// Emits [ServerEvent::Stopped].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{ServerEvent, SshServerBuilder};
+    /// # use std::time::Duration;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().run().await.unwrap();
+    /// let addr = ssh.addr();
+    ///
+    /// ssh.stop().await;
+    /// // Clients fail to connect here.
+    /// ssh.restart().await.unwrap();
+    ///
+    /// assert_eq!(ssh.addr(), addr);
+    /// ssh.wait_for(
+    ///         |e| matches!(e, ServerEvent::Restarted { .. }),
+    ///         Duration::from_secs(1),
+    ///     )
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn stop(&self) {
+        let stopped = {
+            let mut listener = self.listener.lock().unwrap();
+            if listener.stopped_at.is_none() {
+                listener.stopped_at = Some(Instant::now());
+                self.acceptor.stopped.store(true, Ordering::Relaxed);
+                true
+            } else {
+                false
+            }
+        };
+        if stopped {
+            self.acceptor.audit.stopped();
+        }
+        self.acceptor.connections.shutdown().await;
+    }
+
+    /// Accept connections again after [SshServer::stop] or [SshServer::shutdown].
+    ///
+    /// Running server is stopped first. After shutdown the same endpoints are
+    /// bound again, which fails with [SshServerError::AddrInUse] when another
+    /// socket took one of them in the meantime.
+    /// Emits [ServerEvent::Restarted] with the downtime.
+    pub async fn restart(&self) -> anyhow::Result<()> {
+        if self.listener.lock().unwrap().stopped_at.is_none() {
+            self.stop().await;
+        }
+        if self.listener.lock().unwrap().tasks.is_empty() {
+            let tasks = listener::bind_all(&self.endpoints)?
+                .into_iter()
+                .map(|(socket, _)| self.acceptor.listen(socket))
+                .collect();
+            self.listener.lock().unwrap().tasks = tasks;
+        }
+
+        let stopped_at = self.listener.lock().unwrap().stopped_at.take();
+        self.acceptor.stopped.store(false, Ordering::Relaxed);
+        if let Some(stopped_at) = stopped_at {
+            self.acceptor.audit.restarted(stopped_at.elapsed());
+        }
+        Ok(())
+    }
+
+    /// History of connections, authentication attempts and commands.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().run().await.unwrap();
+    ///
+    /// let log = ssh.audit_log();
+    /// assert!(log.connections.is_empty());
+    /// assert!(log.commands.is_empty());
+    /// # }
+    /// ```
+    pub fn audit_log(&self) -> AuditLog {
+        self.acceptor.audit.log()
+    }
+
+    /// Subscribe to live events of the server.
+    ///
+    /// Receiver gets only events emitted after the subscription.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{ServerEvent, SshServerBuilder};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().run().await.unwrap();
+    /// let mut events = ssh.subscribe();
+    ///
+    /// tokio::spawn(async move {
+    ///     while let Ok(event) = events.recv().await {
+    ///         if let ServerEvent::CommandStarted { user, command, .. } = event {
+    ///             println!("{user} run {command}");
+    ///         }
+    ///     }
+    /// });
+    /// # }
+    /// ```
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.acceptor.audit.subscribe()
+    }
+
+    /// Wait for an event matching the predicate.
+    ///
+    /// Events emitted before the call are checked too, so it doesn't matter
+    /// whether the client was faster than the test. Only the last 1024 events
+    /// are kept. Use [SshServer::wait_for_next] to ignore past events,
+    /// for example the same event from before a restart.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ssh_test_server::{ServerEvent, SshServerBuilder};
+    /// # use std::time::Duration;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().run().await.unwrap();
+    ///
+    /// // Start a client here.
+    ///
+    /// ssh.wait_for(
+    ///         |e| matches!(e, ServerEvent::AuthSucceeded { .. }),
+    ///         Duration::from_secs(5),
+    ///     )
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn wait_for<P>(&self, predicate: P, timeout: Duration) -> anyhow::Result<ServerEvent>
+    where
+        P: FnMut(&ServerEvent) -> bool,
+    {
+        self.acceptor.audit.wait_for(predicate, timeout, true).await
+    }
+
+    /// Wait for an event matching the predicate emitted after the call.
+    ///
+    /// Events are collected from the call, before the returned future is
+    /// awaited, so it can be created before the action which triggers the event.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{ServerEvent, SshServerBuilder};
+    /// # use std::time::Duration;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().run().await.unwrap();
+    /// ssh.stop().await;
+    ///
+    /// let restarted = ssh.wait_for_next(
+    ///     |e| matches!(e, ServerEvent::Restarted { .. }),
+    ///     Duration::from_secs(1),
+    /// );
+    /// ssh.restart().await.unwrap();
+    /// restarted.await.unwrap();
+    /// # }
+    /// ```
+    pub fn wait_for_next<P>(
+        &self,
+        predicate: P,
+        timeout: Duration,
+    ) -> impl Future<Output = anyhow::Result<ServerEvent>>
+    where
+        P: FnMut(&ServerEvent) -> bool,
+    {
+        self.acceptor.audit.wait_for(predicate, timeout, false)
+    }
+
+    /// Expect a command to be run by a client.
+    ///
+    /// `command` is a glob pattern matched against the whole command line,
+    /// see [CommandPattern::glob]. Expectations should be met in the order
+    /// of declaration. Check them with [SshServer::verify].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{SshExecuteResult, SshServerBuilder};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().run().await.unwrap();
+    /// ssh.expect_command("systemctl stop *").times(2);
+    /// ssh.expect_command("reboot")
+    ///     .times(1)
+    ///     .returning(|_, _, _| SshExecuteResult::stdout(0, "Rebooting...\n"));
+    ///
+    /// // Client didn't run any command.
+    /// let error = ssh.verify().unwrap_err().to_string();
+    /// assert!(error.contains("missing command `reboot`: expected 1 times, run 0 times"));
+    /// # }
+    /// ```
+    pub fn expect_command(&self, command: &str) -> Expectation {
+        self.acceptor.programs.expectations.add(command)
+    }
+
+    /// Check that clients run exactly the expected commands in the expected order.
+    ///
+    /// Error lists unexpected, missing and out of order commands with users who run them.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        self.acceptor.programs.expectations.verify()
+    }
+
+    /// Open a connection to the server without any socket.
+    ///
+    /// Returned stream is served like a connection accepted by a listener,
+    /// pass it to [russh::client::connect_stream]. The connection is closed
+    /// right away while the server is stopped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// use russh::client;
+    /// use russh_keys::key::PublicKey;
+    /// use std::sync::Arc;
+    ///
+    /// struct Client;
+    ///
+    /// #[async_trait::async_trait]
+    /// impl client::Handler for Client {
+    ///     type Error = russh::Error;
+    ///
+    ///     async fn check_server_key(&mut self, _key: &PublicKey) -> Result<bool, Self::Error> {
+    ///         Ok(true)
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().in_memory().run().await.unwrap();
+    ///
+    /// let config = Arc::new(client::Config::default());
+    /// let stream = ssh.connect_in_memory();
+    /// let _client = client::connect_stream(config, stream, Client).await.unwrap();
+    /// # }
+    /// ```
+    pub fn connect_in_memory(&self) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(IN_MEMORY_BUFFER_SIZE);
+        self.acceptor.accept(server, None);
+        client
+    }
+
+    /// In-memory filesystem served over SFTP.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::SshServerBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let ssh = SshServerBuilder::default().run().await.unwrap();
+    /// ssh.fs().write("/upload/data.txt", "content").unwrap();
+    ///
+    /// assert_eq!(ssh.fs().read_dir("/upload").unwrap(), vec!["data.txt"]);
+    /// # }
+    /// ```
+    pub fn fs(&self) -> MemoryFs {
+        self.acceptor.fs.clone()
     }
 }
 
+/// Format public key as in OpenSSH `known_hosts` and `authorized_keys` files.
+fn openssh_public_key(key: &PublicKey) -> String {
+    let algorithm = match key {
+        PublicKey::RSA { .. } => "ssh-rsa",
+        key => key.name(),
+    };
+    format!("{algorithm} {}", key.public_key_base64())
+}
+
 impl Drop for SshServer {
     fn drop(&mut self) {
-        self.listener.abort();
+        let tasks = std::mem::take(&mut self.listener.lock().unwrap().tasks);
+        if !tasks.is_empty() {
+            tasks.iter().for_each(JoinHandle::abort);
+            self.endpoints.iter().for_each(listener::unbind);
+        }
+        self.acceptor.connections.abort();
     }
 }
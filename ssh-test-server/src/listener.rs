@@ -0,0 +1,226 @@
+use crate::algorithms::{KexSniffer, NegotiatedMap, ServerAlgorithms};
+use crate::audit::Audit;
+use crate::command::Programs;
+use crate::connections::Connections;
+use crate::forward::Forwarding;
+use crate::session::SshConnection;
+use crate::{KeyboardInteractive, MemoryFs, SshServerError, UsersMap};
+use anyhow::{anyhow, Context, Result};
+use russh::server::Config;
+use socket2::{Domain, Type};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{fmt, io};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+/// Address where the server accepts connections.
+///
+/// Parsed from `<ipv4>:<port>`, `[<ipv6>]:<port>` or `unix:<path>`.
+///
+/// # Example
+///
+/// ```
+/// use ssh_test_server::Endpoint;
+///
+/// let endpoint: Endpoint = "[::1]:2222".parse().unwrap();
+/// assert_eq!(endpoint, Endpoint::Tcp("[::1]:2222".parse().unwrap()));
+///
+/// let endpoint: Endpoint = "unix:/tmp/ssh.sock".parse().unwrap();
+/// assert_eq!(endpoint, Endpoint::Unix("/tmp/ssh.sock".into()));
+/// assert_eq!(endpoint.to_string(), "unix:/tmp/ssh.sock");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    /// TCP socket. Unspecified IPv6 address `[::]` accepts IPv4 clients too.
+    Tcp(SocketAddr),
+    /// Unix domain socket.
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Self::Unix(path.into()));
+        }
+        s.parse().map(Self::Tcp).map_err(|_| {
+            anyhow!(
+                "Invalid listen address {s}, expected <ip>:<port>, [<ipv6>]:<port> or unix:<path>"
+            )
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Bound listening socket.
+pub(crate) enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// State shared by all connections, it outlives listeners so the server
+/// can be restarted with the same identity.
+pub(crate) struct Acceptor {
+    pub config: Arc<Config>,
+    pub server_algorithms: Arc<ServerAlgorithms>,
+    pub negotiated: NegotiatedMap,
+    pub users: UsersMap,
+    pub programs: Arc<Programs>,
+    pub keyboard_interactive: Arc<Option<KeyboardInteractive>>,
+    pub fs: MemoryFs,
+    pub forwarding: Arc<Forwarding>,
+    pub audit: Audit,
+    pub connections: Connections,
+    /// Id of the next connection, unique across restarts.
+    pub next_id: AtomicUsize,
+    /// Drop new connections while the server is stopped.
+    pub stopped: AtomicBool,
+}
+
+impl Acceptor {
+    /// Serve ssh session over the stream, the stream is closed while the server is stopped.
+    pub fn accept<S>(&self, stream: S, peer_addr: Option<SocketAddr>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        if self.stopped.load(Ordering::Relaxed) {
+            debug!("Server stopped, dropping connection from {peer_addr:?}");
+            return;
+        }
+        debug!("New connection from {peer_addr:?}");
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let handler = SshConnection::new(
+            id,
+            peer_addr,
+            self.users.clone(),
+            self.programs.clone(),
+            self.keyboard_interactive.clone(),
+            self.fs.clone(),
+            self.forwarding.clone(),
+            self.audit.clone(),
+        );
+        let stream = KexSniffer::new(
+            stream,
+            id,
+            self.server_algorithms.clone(),
+            self.negotiated.clone(),
+        );
+        self.connections
+            .serve(id, self.config.clone(), stream, handler);
+    }
+
+    /// Accept connections from the socket in a new task.
+    pub fn listen(self: &Arc<Self>, socket: Socket) -> JoinHandle<()> {
+        let acceptor = self.clone();
+        tokio::spawn(async move {
+            match socket {
+                Socket::Tcp(socket) => {
+                    while let Ok((stream, addr)) = socket.accept().await {
+                        acceptor.accept(stream, Some(addr));
+                    }
+                }
+                #[cfg(unix)]
+                Socket::Unix(socket) => {
+                    while let Ok((stream, _)) = socket.accept().await {
+                        acceptor.accept(stream, None);
+                    }
+                }
+            }
+            debug!("ssh server stopped");
+        })
+    }
+}
+
+/// Bind the listening socket. Returns the socket and its endpoint, port 0
+/// is replaced by the port picked by the system.
+pub(crate) fn bind(endpoint: &Endpoint) -> Result<(Socket, Endpoint)> {
+    let bound = match endpoint {
+        Endpoint::Tcp(addr) => bind_tcp(*addr).and_then(|socket| {
+            let addr = socket.local_addr()?;
+            Ok((Socket::Tcp(socket), Endpoint::Tcp(addr)))
+        }),
+        #[cfg(unix)]
+        Endpoint::Unix(path) => UnixListener::bind(path)
+            .map(|socket| (Socket::Unix(socket), Endpoint::Unix(path.clone()))),
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix sockets are not supported",
+        )),
+    };
+    match bound {
+        Ok(bound) => Ok(bound),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => Err(SshServerError::AddrInUse {
+            addr: endpoint.to_string(),
+            source: e,
+        }
+        .into()),
+        Err(e) => Err(e).with_context(|| format!("Failed to listen on {endpoint}")),
+    }
+}
+
+fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = socket2::Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Bind sockets of all endpoints, none stays bound on failure.
+pub(crate) fn bind_all(endpoints: &[Endpoint]) -> Result<Vec<(Socket, Endpoint)>> {
+    let mut bound = vec![];
+    for endpoint in endpoints {
+        match bind(endpoint) {
+            Ok(socket) => bound.push(socket),
+            Err(e) => {
+                for (socket, endpoint) in bound {
+                    drop(socket);
+                    unbind(&endpoint);
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(bound)
+}
+
+/// Remove file of the Unix socket after its listener is closed.
+pub(crate) fn unbind(endpoint: &Endpoint) {
+    if let Endpoint::Unix(path) = endpoint {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+impl fmt::Debug for Acceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acceptor")
+            .field("connections", &self.connections)
+            .field("next_id", &self.next_id)
+            .field("stopped", &self.stopped)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,343 @@
+use crate::vfs::FileType;
+use crate::{MemoryFs, SshExecuteIo};
+use anyhow::{anyhow, bail, Result};
+use std::io;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tracing::debug;
+
+/// Size of the buffer for received file data.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Legacy scp protocol run by `scp -t` (sink) and `scp -f` (source) commands.
+pub(crate) struct Scp {
+    sink: bool,
+    recursive: bool,
+    preserve: bool,
+    paths: Vec<String>,
+}
+
+impl Scp {
+    /// Parse scp command line. Returns [None] when it isn't an scp transfer.
+    pub fn parse(command: &[u8]) -> Option<Self> {
+        let mut cmdline = String::from_utf8_lossy(command).to_string();
+        let mut words = cmdline_words_parser::parse_posix(&mut cmdline);
+        if words.next()? != "scp" {
+            return None;
+        }
+
+        let mut scp = Self {
+            sink: false,
+            recursive: false,
+            preserve: false,
+            paths: vec![],
+        };
+        let (mut to, mut from) = (false, false);
+        let mut options = true;
+        for word in words {
+            if options && word == "--" {
+                options = false;
+            } else if options && word.len() > 1 && word.starts_with('-') {
+                for flag in word[1..].chars() {
+                    match flag {
+                        't' => to = true,
+                        'f' => from = true,
+                        'r' => scp.recursive = true,
+                        'p' => scp.preserve = true,
+                        // Target should be directory, verbose, quiet
+                        'd' | 'v' | 'q' => {}
+                        _ => return None,
+                    }
+                }
+            } else {
+                scp.paths.push(word.to_string());
+            }
+        }
+
+        if to == from || scp.paths.is_empty() {
+            return None;
+        }
+        scp.sink = to;
+        Some(scp)
+    }
+
+    /// Run transfer over stdin and stdout of the command. Returns exit status.
+    pub async fn run(&self, io: &mut SshExecuteIo<'_>, fs: &MemoryFs, session_id: usize) -> u32 {
+        let mut reader = BufReader::new(&mut *io.stdin);
+        let mut writer = &mut *io.stdout;
+
+        let result = if self.sink {
+            self.sink(&mut reader, &mut writer, fs).await
+        } else {
+            self.source(&mut reader, &mut writer, fs).await
+        };
+        let _ = writer.flush().await;
+
+        match result {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                debug!(session_id, "scp failed: {e}");
+                1
+            }
+        }
+    }
+
+    /// Receive files from the client. Returns false when some files failed.
+    async fn sink<R, W>(&self, reader: &mut R, writer: &mut W, fs: &MemoryFs) -> Result<bool>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let target = &self.paths[0];
+        let mut dirs: Vec<String> = vec![];
+        let mut modified = None;
+        let mut success = true;
+        let mut eof = false;
+
+        writer.write_all(b"\0").await?;
+        while !eof {
+            let mut line = vec![];
+            if reader.read_until(b'\n', &mut line).await? == 0 {
+                break;
+            }
+            if line.pop() != Some(b'\n') {
+                bail!("unexpected end of record");
+            }
+            let line = String::from_utf8_lossy(&line).to_string();
+            let Some(kind) = line.chars().next() else {
+                bail!("empty record");
+            };
+            let record = &line[1..];
+
+            match kind {
+                'T' => {
+                    let mtime = record.split(' ').next().unwrap_or_default();
+                    modified = Some(mtime.parse().map_err(|_| anyhow!("bad time: {line}"))?);
+                    writer.write_all(b"\0").await?;
+                }
+                'C' | 'D' => {
+                    let mut fields = record.splitn(3, ' ');
+                    let (Some(mode), Some(size), Some(name)) =
+                        (fields.next(), fields.next(), fields.next())
+                    else {
+                        bail!("bad record: {line}");
+                    };
+                    let mode = u32::from_str_radix(mode, 8).map_err(|_| anyhow!("bad mode"))?;
+                    let size: u64 = size.parse().map_err(|_| anyhow!("bad size"))?;
+                    if name.is_empty() || name.contains('/') || name == ".." {
+                        bail!("invalid name: {name}");
+                    }
+
+                    let path = match dirs.last() {
+                        Some(dir) => join(dir, name),
+                        None if is_dir(fs, target) => join(target, name),
+                        None => target.clone(),
+                    };
+
+                    let stored = if kind == 'D' {
+                        if !self.recursive {
+                            bail!("received directory without -r");
+                        }
+                        let created = if is_dir(fs, &path) {
+                            fs.check_access(&path, 0o200)
+                        } else {
+                            fs.check_parent_writable(&path)
+                                .and_then(|_| fs.create_dir(&path))
+                        };
+                        // The client skips contents of a directory it got an
+                        // error for, so there won't be an end record for it.
+                        if created.is_ok() {
+                            dirs.push(path.clone());
+                        }
+                        created
+                    } else {
+                        writer.write_all(b"\0").await?;
+                        let stored = receive_file(reader, fs, &path, size).await?;
+                        // Some clients close the channel instead of sending
+                        // the final status byte.
+                        let mut status = [0];
+                        eof = reader.read(&mut status).await? == 0;
+                        stored
+                    };
+                    let stored = stored
+                        .and_then(|_| fs.set_permissions(&path, mode))
+                        .and_then(|_| match modified.take() {
+                            Some(mtime) => fs.set_modified(&path, mtime),
+                            None => Ok(()),
+                        });
+
+                    match stored {
+                        Ok(_) => writer.write_all(b"\0").await?,
+                        Err(e) => {
+                            success = false;
+                            let msg = format!("\x01scp: {path}: {e}\n");
+                            writer.write_all(msg.as_bytes()).await?;
+                        }
+                    }
+                }
+                'E' => {
+                    dirs.pop();
+                    writer.write_all(b"\0").await?;
+                }
+                '\x01' => {
+                    debug!("scp warning: {record}");
+                    success = false;
+                }
+                _ => bail!("unexpected record: {line}"),
+            }
+        }
+
+        Ok(success)
+    }
+
+    /// Send files to the client. Returns false when some files failed.
+    async fn source<R, W>(&self, reader: &mut R, writer: &mut W, fs: &MemoryFs) -> Result<bool>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        enum Item {
+            Path(String),
+            EndDir,
+        }
+
+        enum Contents {
+            Dir(Vec<String>),
+            File(Vec<u8>),
+        }
+
+        let mut pending: Vec<_> = self.paths.iter().rev().cloned().map(Item::Path).collect();
+        let mut success = true;
+
+        wait_ack(reader).await?;
+        while let Some(item) = pending.pop() {
+            let path = match item {
+                Item::Path(path) => path,
+                Item::EndDir => {
+                    writer.write_all(b"E\n").await?;
+                    wait_ack(reader).await?;
+                    continue;
+                }
+            };
+
+            let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+            let metadata = match fs.metadata(&path) {
+                Ok(metadata) if metadata.file_type == FileType::Dir && !self.recursive => {
+                    Err(format!("{path}: not a regular file"))
+                }
+                Ok(metadata) => Ok(metadata),
+                Err(e) => Err(format!("{path}: {e}")),
+            };
+            // Contents are read before any record is sent, so an error
+            // skips only this entry.
+            let loaded = metadata.and_then(|metadata| {
+                let contents = if metadata.file_type == FileType::Dir {
+                    fs.check_access(&path, 0o400)
+                        .and_then(|_| fs.read_dir(&path))
+                        .map(Contents::Dir)
+                } else {
+                    fs.check_access(&path, 0o400)
+                        .and_then(|_| fs.read(&path))
+                        .map(Contents::File)
+                };
+                contents
+                    .map(|contents| (metadata, contents))
+                    .map_err(|e| format!("{path}: {e}"))
+            });
+            let (metadata, contents) = match loaded {
+                Ok(loaded) => loaded,
+                Err(msg) => {
+                    success = false;
+                    writer
+                        .write_all(format!("\x01scp: {msg}\n").as_bytes())
+                        .await?;
+                    continue;
+                }
+            };
+
+            if self.preserve {
+                let time = format!("T{0} 0 {0} 0\n", metadata.modified);
+                writer.write_all(time.as_bytes()).await?;
+                wait_ack(reader).await?;
+            }
+
+            match contents {
+                Contents::Dir(entries) => {
+                    let record = format!("D{:04o} 0 {name}\n", metadata.permissions);
+                    writer.write_all(record.as_bytes()).await?;
+                    wait_ack(reader).await?;
+
+                    pending.push(Item::EndDir);
+                    for entry in entries.into_iter().rev() {
+                        pending.push(Item::Path(join(&path, &entry)));
+                    }
+                }
+                Contents::File(data) => {
+                    let record = format!("C{:04o} {} {name}\n", metadata.permissions, data.len());
+                    writer.write_all(record.as_bytes()).await?;
+                    wait_ack(reader).await?;
+                    writer.write_all(&data).await?;
+                    writer.write_all(b"\0").await?;
+                    wait_ack(reader).await?;
+                }
+            }
+        }
+
+        Ok(success)
+    }
+}
+
+/// Store `size` bytes of file data in chunks. Data is consumed even when
+/// the file can't be stored, the error is returned in the inner result.
+async fn receive_file<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    fs: &MemoryFs,
+    path: &str,
+    size: u64,
+) -> Result<io::Result<()>> {
+    let mut stored = if fs.exists(path) {
+        fs.check_access(path, 0o200)
+    } else {
+        fs.check_parent_writable(path)
+    }
+    .and_then(|_| fs.open(path, true, true, false, None).map(|_| ()));
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut offset = 0;
+    while offset < size {
+        let n = (size - offset).min(CHUNK_SIZE as u64) as usize;
+        reader.read_exact(&mut chunk[..n]).await?;
+        if stored.is_ok() {
+            stored = fs.write_at(path, offset, &chunk[..n]).map(|_| ());
+        }
+        offset += n as u64;
+    }
+    Ok(stored)
+}
+
+/// Wait for the confirmation of the last record.
+async fn wait_ack<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<()> {
+    let mut status = [0];
+    reader.read_exact(&mut status).await?;
+    if status[0] == 0 {
+        return Ok(());
+    }
+
+    let mut msg = vec![];
+    reader.read_until(b'\n', &mut msg).await?;
+    Err(anyhow!(
+        "client error: {}",
+        String::from_utf8_lossy(&msg).trim_end()
+    ))
+}
+
+fn is_dir(fs: &MemoryFs, path: &str) -> bool {
+    fs.metadata(path)
+        .map(|m| m.file_type == FileType::Dir)
+        .unwrap_or(false)
+}
+
+fn join(dir: &str, name: &str) -> String {
+    format!("{}/{name}", dir.trim_end_matches('/'))
+}
@@ -1,31 +1,153 @@
-use crate::{command, SshExecuteHandler, UsersMap};
+use crate::audit::{Audit, AuthOutcome};
+use crate::auth::{AuthProgress, KeyboardInteractiveState};
+use crate::command::{CommandEnv, Programs, TerminalOutput};
+use crate::forward::Forwarding;
+use crate::sftp::SftpSession;
+use crate::{command, KeyboardInteractive, LocalForward, MemoryFs, SshExecuteIo, UsersMap};
 use anyhow::Result;
 use async_trait::async_trait;
 use russh::server::{Auth, Handler, Msg, Response, Session};
-use russh::{Channel, ChannelId, ChannelMsg, CryptoVec};
+use russh::{Channel, ChannelId, ChannelMsg, CryptoVec, MethodSet};
 use russh_keys::key::PublicKey;
-use std::collections::HashMap;
 use std::mem;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio::task::JoinSet;
 use tracing::debug;
 
-pub type ProgramsMap = Arc<HashMap<String, Box<SshExecuteHandler>>>;
-
 pub(crate) struct SshConnection {
     id: usize,
     users: UsersMap,
     user: Option<String>,
-    programs: ProgramsMap,
+    programs: Arc<Programs>,
+    keyboard_interactive: Arc<Option<KeyboardInteractive>>,
+    keyboard_interactive_state: Option<KeyboardInteractiveState>,
+    auth_progress: Option<AuthProgress>,
+    fs: MemoryFs,
+    forwarding: Arc<Forwarding>,
+    audit: Audit,
+    /// Tasks serving channels, aborted when the connection is dropped.
+    channels: JoinSet<()>,
 }
 
 impl SshConnection {
-    pub fn new(id: usize, users: UsersMap, programs: ProgramsMap) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: usize,
+        peer_addr: Option<SocketAddr>,
+        users: UsersMap,
+        programs: Arc<Programs>,
+        keyboard_interactive: Arc<Option<KeyboardInteractive>>,
+        fs: MemoryFs,
+        forwarding: Arc<Forwarding>,
+        audit: Audit,
+    ) -> Self {
+        audit.connected(id, peer_addr);
         Self {
             id,
             users,
             user: None,
             programs,
+            keyboard_interactive,
+            keyboard_interactive_state: None,
+            auth_progress: None,
+            fs,
+            forwarding,
+            audit,
+            channels: JoinSet::new(),
+        }
+    }
+
+    /// Forget tasks of channels which already finished.
+    fn reap_channels(&mut self) {
+        while self.channels.try_join_next().is_some() {}
+    }
+
+    /// Methods required in the next authentication step of the user.
+    /// Returns [None] when user doesn't require multiple factors.
+    fn pending_methods(&self, user: &str) -> Option<MethodSet> {
+        let step = self.current_auth_step(user);
+        self.users
+            .lock()
+            .unwrap()
+            .get(user)
+            .and_then(|u| u.required_auth_methods().get(step).copied())
+    }
+
+    fn current_auth_step(&self, user: &str) -> usize {
+        match &self.auth_progress {
+            Some(progress) if progress.user == user => progress.step,
+            _ => 0,
+        }
+    }
+
+    fn method_allowed(&self, user: &str, method: MethodSet) -> bool {
+        self.pending_methods(user)
+            .map(|methods| methods.contains(method))
+            .unwrap_or(true)
+    }
+
+    fn reject(&self, user: &str) -> Auth {
+        Auth::Reject {
+            proceed_with_methods: self.pending_methods(user),
+        }
+    }
+
+    fn factor_failed(&self, user: &str, method: MethodSet) -> Auth {
+        self.audit
+            .auth(self.id, user, method, AuthOutcome::Rejected);
+        self.reject(user)
+    }
+
+    fn factor_succeeded(&mut self, user: &str, method: MethodSet) -> Auth {
+        let steps = self
+            .users
+            .lock()
+            .unwrap()
+            .get(user)
+            .map(|u| u.required_auth_methods().len())
+            .unwrap_or(0);
+        let step = self.current_auth_step(user);
+
+        if steps > 0 {
+            if !self.method_allowed(user, method) {
+                debug!("user={user} step={step} method {method:?} not allowed");
+                return self.factor_failed(user, method);
+            }
+            if step + 1 < steps {
+                self.auth_progress = Some(AuthProgress {
+                    user: user.to_string(),
+                    step: step + 1,
+                });
+                debug!("user={user} step={step} method {method:?} step passed");
+                self.audit
+                    .auth(self.id, user, method, AuthOutcome::StepPassed);
+                return self.reject(user);
+            }
         }
+
+        self.auth_progress = None;
+        self.user = Some(user.to_string());
+        debug!("user={user} method {method:?} Accepted");
+        self.audit
+            .auth(self.id, user, method, AuthOutcome::Accepted);
+        Auth::Accept
+    }
+
+    fn is_authorized_key(&self, user: &str, public_key: &PublicKey) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get(user)
+            .map(|u| u.authorized_keys().contains(public_key))
+            .unwrap_or(false)
+    }
+}
+
+impl Drop for SshConnection {
+    fn drop(&mut self) {
+        self.forwarding.remote_forwards.remove_connection(self.id);
+        self.audit.disconnected(self.id);
     }
 }
 
@@ -35,27 +157,46 @@ impl Handler for SshConnection {
 
     async fn auth_none(&mut self, user: &str) -> Result<Auth, Self::Error> {
         debug!("auth_none user={user}");
-        Ok(Auth::Reject {
-            proceed_with_methods: None,
-        })
+        self.audit.auth_attempted(self.id, user, MethodSet::NONE);
+        Ok(self.factor_failed(user, MethodSet::NONE))
     }
 
     async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
-        let users = self.users.lock().unwrap();
-        if let Some(u) = users.get(user) {
-            if password == u.password() {
-                self.user = Some(u.login().to_string());
-                drop(users);
-                debug!("auth_password user={user} password={password} Accepted");
-                return Ok(Auth::Accept);
-            }
+        self.audit
+            .auth_attempted(self.id, user, MethodSet::PASSWORD);
+        let correct = self
+            .users
+            .lock()
+            .unwrap()
+            .get(user)
+            .map(|u| password == u.password())
+            .unwrap_or(false);
+
+        if correct {
+            debug!("auth_password user={user} password={password} Correct");
+            return Ok(self.factor_succeeded(user, MethodSet::PASSWORD));
         }
 
-        drop(users);
         debug!("auth_password user={user} password={password} Rejected");
-        Ok(Auth::Reject {
-            proceed_with_methods: None,
-        })
+        Ok(self.factor_failed(user, MethodSet::PASSWORD))
+    }
+
+    async fn auth_publickey_offered(
+        &mut self,
+        user: &str,
+        public_key: &PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        let authorized = self.is_authorized_key(user, public_key);
+        debug!(
+            "auth_publickey_offered user={user} public_key={} authorized={authorized}",
+            public_key.fingerprint()
+        );
+
+        if authorized && self.method_allowed(user, MethodSet::PUBLICKEY) {
+            Ok(Auth::Accept)
+        } else {
+            Ok(self.reject(user))
+        }
     }
 
     async fn auth_publickey(
@@ -63,23 +204,78 @@ impl Handler for SshConnection {
         user: &str,
         public_key: &PublicKey,
     ) -> Result<Auth, Self::Error> {
-        debug!("auth_publickey user={user} public_key={public_key:?}");
+        self.audit
+            .auth_attempted(self.id, user, MethodSet::PUBLICKEY);
+        if self.is_authorized_key(user, public_key) {
+            debug!(
+                "auth_publickey user={user} public_key={} Correct",
+                public_key.fingerprint()
+            );
+            return Ok(self.factor_succeeded(user, MethodSet::PUBLICKEY));
+        }
 
-        Ok(Auth::Reject {
-            proceed_with_methods: None,
-        })
+        debug!(
+            "auth_publickey user={user} public_key={} Rejected",
+            public_key.fingerprint()
+        );
+        Ok(self.factor_failed(user, MethodSet::PUBLICKEY))
     }
 
     async fn auth_keyboard_interactive(
         &mut self,
         user: &str,
         submethods: &str,
-        _response: Option<Response<'async_trait>>,
+        response: Option<Response<'async_trait>>,
     ) -> Result<Auth, Self::Error> {
         debug!("auth_keyboard_interactive user={user} submethods={submethods:?}");
-        Ok(Auth::Reject {
-            proceed_with_methods: None,
-        })
+
+        let Some(response) = response else {
+            self.audit
+                .auth_attempted(self.id, user, MethodSet::KEYBOARD_INTERACTIVE);
+            let script = self.users.lock().unwrap().get(user).and_then(|u| {
+                u.keyboard_interactive()
+                    .or(self.keyboard_interactive.as_ref().as_ref())
+                    .cloned()
+            });
+
+            let Some(script) = script else {
+                debug!("auth_keyboard_interactive user={user} Rejected");
+                return Ok(self.factor_failed(user, MethodSet::KEYBOARD_INTERACTIVE));
+            };
+            if script.rounds() == 0 {
+                debug!("auth_keyboard_interactive user={user} Correct");
+                return Ok(self.factor_succeeded(user, MethodSet::KEYBOARD_INTERACTIVE));
+            }
+
+            let info_request = script.info_request(0);
+            self.keyboard_interactive_state = Some(KeyboardInteractiveState {
+                user: user.to_string(),
+                script,
+                round: 0,
+            });
+            return Ok(info_request);
+        };
+
+        let Some(mut state) = self.keyboard_interactive_state.take() else {
+            return Ok(self.factor_failed(user, MethodSet::KEYBOARD_INTERACTIVE));
+        };
+        if state.user != user || !state.script.check_answers(state.round, response) {
+            debug!(
+                "auth_keyboard_interactive user={user} round={} Rejected",
+                state.round
+            );
+            return Ok(self.factor_failed(user, MethodSet::KEYBOARD_INTERACTIVE));
+        }
+
+        state.round += 1;
+        if state.round < state.script.rounds() {
+            let info_request = state.script.info_request(state.round);
+            self.keyboard_interactive_state = Some(state);
+            return Ok(info_request);
+        }
+
+        debug!("auth_keyboard_interactive user={user} Correct");
+        Ok(self.factor_succeeded(user, MethodSet::KEYBOARD_INTERACTIVE))
     }
 
     async fn auth_succeeded(&mut self, _session: &mut Session) -> Result<(), Self::Error> {
@@ -103,13 +299,23 @@ impl Handler for SshConnection {
     ) -> Result<bool, Self::Error> {
         let session_id = self.id;
         debug!(session_id, "channel_open_session channel={}", channel.id());
+        self.audit.channel_opened(session_id, "session");
         let handle = session.handle();
-        let user = self.user.clone().unwrap();
-        let users = self.users.clone();
-        let programs = self.programs.clone();
-        tokio::spawn(async move {
+        let env = CommandEnv {
+            connection_id: session_id,
+            user: self.user.clone().unwrap(),
+            users: self.users.clone(),
+            programs: self.programs.clone(),
+            audit: self.audit.clone(),
+            fs: self.fs.clone(),
+        };
+        let audit = self.audit.clone();
+        self.reap_channels();
+        self.channels.spawn(async move {
             let id = channel.id();
             let mut command_buf = vec![];
+            let mut sftp = false;
+            let mut pty = false;
 
             while let Some(msg) = channel.wait().await {
                 match msg {
@@ -123,6 +329,8 @@ impl Handler for SshConnection {
                         terminal_modes,
                     } => {
                         debug!(session_id, "request-pty want_reply={want_reply} term={term} col/row={col_width}/{row_height} pix width/height={pix_width}/{pix_height} modes={terminal_modes:?}");
+                        pty = true;
+                        audit.pty_requested(session_id, &term, col_width, row_height);
                         if want_reply {
                             handle.channel_success(id).await.unwrap();
                         }
@@ -148,10 +356,13 @@ impl Handler for SshConnection {
                                 stdout.push(b'\n');
                                 handle.data(id, mem::take(&mut stdout)).await.unwrap();
                                 let cmd = mem::take(&mut command_buf);
-                                command::execute_command(
-                                    cmd, id, &handle, &user, &users, &programs,
-                                )
-                                .await;
+                                let mut io = SshExecuteIo::new(
+                                    tokio::io::empty(),
+                                    TerminalOutput::new(channel.make_writer(), pty),
+                                    TerminalOutput::new(channel.make_writer_ext(Some(1)), pty),
+                                );
+                                command::execute_command(cmd, id, &handle, &mut io, &env, true)
+                                    .await;
                                 handle.data(id, CryptoVec::from_slice(b"$ ")).await.unwrap();
                             } else {
                                 command_buf.push(*b);
@@ -176,15 +387,37 @@ impl Handler for SshConnection {
                             handle.channel_success(id).await.unwrap();
                         }
 
-                        command::execute_command(command, id, &handle, &user, &users, &programs)
-                            .await;
+                        let stdout = TerminalOutput::new(channel.make_writer(), pty);
+                        let stderr = TerminalOutput::new(channel.make_writer_ext(Some(1)), pty);
+                        let mut io = SshExecuteIo::new(channel.make_reader(), stdout, stderr);
+                        command::execute_command(command, id, &handle, &mut io, &env, false).await;
                         handle.close(id).await.unwrap();
                     }
+                    ChannelMsg::RequestSubsystem { want_reply, name } => {
+                        debug!(session_id, "subsystem want_reply={want_reply} name={name}");
+                        if name == "sftp" {
+                            if want_reply {
+                                handle.channel_success(id).await.unwrap();
+                            }
+                            sftp = true;
+                            break;
+                        }
+                        if want_reply {
+                            handle.channel_failure(id).await.unwrap();
+                        }
+                    }
                     _ => {
                         debug!(session_id, "msg={msg:?}");
                     }
                 }
             }
+
+            if sftp {
+                let sftp = SftpSession::new(session_id, env.fs);
+                russh_sftp::server::run(channel.into_stream(), sftp).await;
+                debug!(session_id, "sftp started");
+                return;
+            }
             debug!(session_id, "closed");
         });
 
@@ -209,10 +442,24 @@ impl Handler for SshConnection {
         port_to_connect: u32,
         originator_address: &str,
         originator_port: u32,
-        _session: &mut Session,
+        session: &mut Session,
     ) -> Result<bool, Self::Error> {
         debug!("channel_open_direct_tcpip channel={} host_to_connect={host_to_connect} port_to_connect={port_to_connect} originator_address={originator_address} originator_port={originator_port}", channel.id());
-        Ok(false)
+        self.audit.channel_opened(self.id, "direct-tcpip");
+        let forward = LocalForward {
+            connection_id: self.id,
+            user: self.user.clone().unwrap_or_default(),
+            host: host_to_connect.to_string(),
+            port: port_to_connect,
+            originator_address: originator_address.to_string(),
+            originator_port,
+            accepted: false,
+        };
+        self.reap_channels();
+        Ok(self
+            .forwarding
+            .open_local(channel, session.handle(), forward, &mut self.channels)
+            .await)
     }
 
     async fn channel_open_forwarded_tcpip(
@@ -228,6 +475,47 @@ impl Handler for SshConnection {
         Ok(false)
     }
 
+    async fn tcpip_forward(
+        &mut self,
+        address: &str,
+        port: &mut u32,
+        session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        let session_id = self.id;
+        debug!(session_id, "tcpip_forward address={address} port={port}");
+        let Some(user) = &self.user else {
+            return Ok(false);
+        };
+        let allocated =
+            self.forwarding
+                .remote_forwards
+                .add(self.id, user, address, *port, session.handle());
+        match allocated {
+            Some(allocated) => {
+                *port = allocated;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn cancel_tcpip_forward(
+        &mut self,
+        address: &str,
+        port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        let session_id = self.id;
+        debug!(
+            session_id,
+            "cancel_tcpip_forward address={address} port={port}"
+        );
+        Ok(self
+            .forwarding
+            .remote_forwards
+            .cancel(self.id, address, port))
+    }
+
     fn adjust_window(&mut self, channel: ChannelId, current: u32) -> u32 {
         debug!("adjust_window {channel} current={current}");
         current
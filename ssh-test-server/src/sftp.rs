@@ -0,0 +1,386 @@
+use crate::vfs::{FileType, Metadata};
+use crate::MemoryFs;
+use async_trait::async_trait;
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, FileMode, Handle, Name, OpenFlags, Status, StatusCode,
+    Version,
+};
+use russh_sftp::server::Handler;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use tracing::debug;
+
+/// Handle opened by the client.
+enum OpenHandle {
+    File { path: String, append: bool },
+    Dir { path: String, read: bool },
+}
+
+/// Sftp subsystem of a single channel.
+pub(crate) struct SftpSession {
+    session_id: usize,
+    fs: MemoryFs,
+    handles: HashMap<String, OpenHandle>,
+    next_handle: u64,
+}
+
+impl SftpSession {
+    pub fn new(session_id: usize, fs: MemoryFs) -> Self {
+        Self {
+            session_id,
+            fs,
+            handles: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    fn add_handle(&mut self, handle: OpenHandle) -> String {
+        let name = self.next_handle.to_string();
+        self.next_handle += 1;
+        self.handles.insert(name.clone(), handle);
+        name
+    }
+
+    fn file_path(&self, handle: &str) -> Result<(&str, bool), StatusCode> {
+        match self.handles.get(handle) {
+            Some(OpenHandle::File { path, append }) => Ok((path, *append)),
+            _ => Err(StatusCode::Failure),
+        }
+    }
+
+    fn handle_path(&self, handle: &str) -> Result<&str, StatusCode> {
+        match self.handles.get(handle) {
+            Some(OpenHandle::File { path, .. }) | Some(OpenHandle::Dir { path, .. }) => Ok(path),
+            None => Err(StatusCode::Failure),
+        }
+    }
+
+    fn set_attributes(&self, path: &str, attrs: &FileAttributes) -> io::Result<()> {
+        if let Some(size) = attrs.size {
+            self.fs.set_len(path, size)?;
+        }
+        if let Some(permissions) = attrs.permissions {
+            self.fs.set_permissions(path, permissions)?;
+        }
+        if let Some(mtime) = attrs.mtime {
+            self.fs.set_modified(path, mtime)?;
+        }
+        Ok(())
+    }
+
+    fn log_error(&self, operation: &str, path: &str, e: io::Error) -> StatusCode {
+        let session_id = self.session_id;
+        debug!(session_id, "sftp {operation} {path}: {e}");
+        match e.kind() {
+            ErrorKind::NotFound => StatusCode::NoSuchFile,
+            ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+            _ => StatusCode::Failure,
+        }
+    }
+}
+
+fn ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+fn attributes(metadata: &Metadata) -> FileAttributes {
+    let mode = match metadata.file_type {
+        FileType::File => FileMode::REG,
+        FileType::Dir => FileMode::DIR,
+        FileType::Symlink => FileMode::LNK,
+    };
+    FileAttributes {
+        size: Some(metadata.len),
+        uid: Some(0),
+        user: None,
+        gid: Some(0),
+        group: None,
+        permissions: Some(metadata.permissions | mode.bits()),
+        atime: Some(metadata.modified),
+        mtime: Some(metadata.modified),
+    }
+}
+
+fn name(id: u32, path: String) -> Name {
+    Name {
+        id,
+        files: vec![File::dummy(path)],
+    }
+}
+
+#[async_trait]
+impl Handler for SftpSession {
+    type Error = StatusCode;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported
+    }
+
+    async fn init(
+        &mut self,
+        version: u32,
+        _extensions: HashMap<String, String>,
+    ) -> Result<Version, Self::Error> {
+        let session_id = self.session_id;
+        debug!(session_id, "sftp init version={version}");
+        Ok(Version::new())
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        attrs: FileAttributes,
+    ) -> Result<Handle, Self::Error> {
+        let session_id = self.session_id;
+        debug!(session_id, "sftp open {filename} flags={pflags:?}");
+        let write = pflags.intersects(OpenFlags::WRITE | OpenFlags::APPEND);
+        let create = pflags.contains(OpenFlags::CREATE);
+
+        let access = if self.fs.exists(&filename) {
+            let mut mask = 0;
+            if pflags.contains(OpenFlags::READ) {
+                mask |= 0o400;
+            }
+            if write {
+                mask |= 0o200;
+            }
+            self.fs.check_access(&filename, mask)
+        } else if create {
+            self.fs.check_parent_writable(&filename)
+        } else {
+            Ok(())
+        };
+        let path = access
+            .and_then(|_| {
+                self.fs.open(
+                    &filename,
+                    create,
+                    pflags.contains(OpenFlags::TRUNCATE),
+                    pflags.contains(OpenFlags::EXCLUDE),
+                    attrs.permissions,
+                )
+            })
+            .map_err(|e| self.log_error("open", &filename, e))?;
+
+        let handle = self.add_handle(OpenHandle::File {
+            path,
+            append: pflags.contains(OpenFlags::APPEND),
+        });
+        Ok(Handle { id, handle })
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        self.handles.remove(&handle).ok_or(StatusCode::Failure)?;
+        Ok(ok(id))
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        len: u32,
+    ) -> Result<Data, Self::Error> {
+        let (path, _) = self.file_path(&handle)?;
+        let data = self
+            .fs
+            .read_at(path, offset, len as usize)
+            .map_err(|e| self.log_error("read", path, e))?;
+        if data.is_empty() && len > 0 {
+            return Err(StatusCode::Eof);
+        }
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status, Self::Error> {
+        let (path, append) = self.file_path(&handle)?;
+        let offset = if append {
+            self.fs.metadata(path).map(|m| m.len).unwrap_or(offset)
+        } else {
+            offset
+        };
+        self.fs
+            .write_at(path, offset, &data)
+            .map_err(|e| self.log_error("write", path, e))?;
+        Ok(ok(id))
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let metadata = self
+            .fs
+            .symlink_metadata(&path)
+            .map_err(|e| self.log_error("lstat", &path, e))?;
+        Ok(Attrs {
+            id,
+            attrs: attributes(&metadata),
+        })
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+        let path = self.handle_path(&handle)?.to_string();
+        self.stat(id, path).await
+    }
+
+    async fn setstat(
+        &mut self,
+        id: u32,
+        path: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        self.set_attributes(&path, &attrs)
+            .map_err(|e| self.log_error("setstat", &path, e))?;
+        Ok(ok(id))
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        handle: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        let path = self.handle_path(&handle)?.to_string();
+        self.setstat(id, path, attrs).await
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+        let session_id = self.session_id;
+        debug!(session_id, "sftp opendir {path}");
+        let resolved = self
+            .fs
+            .check_access(&path, 0o400)
+            .and_then(|_| self.fs.read_dir_metadata(&path))
+            .and_then(|_| self.fs.canonicalize(&path))
+            .map_err(|e| self.log_error("opendir", &path, e))?;
+        let handle = self.add_handle(OpenHandle::Dir {
+            path: resolved,
+            read: false,
+        });
+        Ok(Handle { id, handle })
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+        let Some(OpenHandle::Dir { path, read }) = self.handles.get_mut(&handle) else {
+            return Err(StatusCode::Failure);
+        };
+        if *read {
+            return Err(StatusCode::Eof);
+        }
+        *read = true;
+        let path = path.clone();
+
+        let entries = self
+            .fs
+            .read_dir_metadata(&path)
+            .map_err(|e| self.log_error("readdir", &path, e))?;
+        let files = entries
+            .iter()
+            .map(|(name, metadata)| File::new(name.as_str(), attributes(metadata)))
+            .collect();
+        Ok(Name { id, files })
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        self.fs
+            .check_parent_writable(&filename)
+            .and_then(|_| self.fs.remove_file(&filename))
+            .map_err(|e| self.log_error("remove", &filename, e))?;
+        Ok(ok(id))
+    }
+
+    async fn mkdir(
+        &mut self,
+        id: u32,
+        path: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        self.fs
+            .check_parent_writable(&path)
+            .and_then(|_| self.fs.create_dir(&path))
+            .and_then(|_| match attrs.permissions {
+                Some(permissions) => self.fs.set_permissions(&path, permissions),
+                None => Ok(()),
+            })
+            .map_err(|e| self.log_error("mkdir", &path, e))?;
+        Ok(ok(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        self.fs
+            .check_parent_writable(&path)
+            .and_then(|_| self.fs.remove_dir(&path))
+            .map_err(|e| self.log_error("rmdir", &path, e))?;
+        Ok(ok(id))
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        let path = self
+            .fs
+            .canonicalize(&path)
+            .map_err(|e| self.log_error("realpath", &path, e))?;
+        Ok(name(id, path))
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let metadata = self
+            .fs
+            .metadata(&path)
+            .map_err(|e| self.log_error("stat", &path, e))?;
+        Ok(Attrs {
+            id,
+            attrs: attributes(&metadata),
+        })
+    }
+
+    async fn rename(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> Result<Status, Self::Error> {
+        self.fs
+            .check_parent_writable(&oldpath)
+            .and_then(|_| self.fs.check_parent_writable(&newpath))
+            .and_then(|_| match self.fs.symlink_metadata(&newpath) {
+                Ok(_) => Err(io::Error::new(ErrorKind::AlreadyExists, "File exists")),
+                Err(_) => self.fs.rename(&oldpath, &newpath),
+            })
+            .map_err(|e| self.log_error("rename", &oldpath, e))?;
+        Ok(ok(id))
+    }
+
+    async fn readlink(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        let target = self
+            .fs
+            .read_link(&path)
+            .map_err(|e| self.log_error("readlink", &path, e))?;
+        Ok(name(id, target))
+    }
+
+    async fn symlink(
+        &mut self,
+        id: u32,
+        linkpath: String,
+        targetpath: String,
+    ) -> Result<Status, Self::Error> {
+        // OpenSSH sends the arguments in reversed order, the target comes first.
+        let (target, link) = (linkpath, targetpath);
+        self.fs
+            .check_parent_writable(&link)
+            .and_then(|_| self.fs.symlink(&target, &link))
+            .map_err(|e| self.log_error("symlink", &link, e))?;
+        Ok(ok(id))
+    }
+}
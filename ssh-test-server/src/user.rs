@@ -1,3 +1,8 @@
+use crate::KeyboardInteractive;
+use anyhow::{anyhow, Result};
+use russh::MethodSet;
+use russh_keys::key::PublicKey;
+
 /// Ssh user.
 ///
 /// # Example
@@ -12,6 +17,9 @@ pub struct User {
     login: String,
     password: String,
     admin: bool,
+    authorized_keys: Vec<PublicKey>,
+    keyboard_interactive: Option<KeyboardInteractive>,
+    required_auth_methods: Vec<MethodSet>,
 }
 
 impl User {
@@ -30,6 +38,9 @@ impl User {
             login: login.into(),
             password: password.into(),
             admin: false,
+            authorized_keys: Vec::new(),
+            keyboard_interactive: None,
+            required_auth_methods: Vec::new(),
         }
     }
 
@@ -110,4 +121,107 @@ impl User {
     pub fn set_password(&mut self, new_password: &str) {
         self.password = new_password.to_string();
     }
+
+    /// Add a public key that is allowed to log in as this user.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::User;
+    /// use russh_keys::key::KeyPair;
+    ///
+    /// let key = KeyPair::generate_ed25519();
+    /// let mut u = User::new("a", "12");
+    /// u.add_authorized_key(key.clone_public_key().unwrap());
+    /// assert_eq!(u.authorized_keys().len(), 1);
+    /// ```
+    pub fn add_authorized_key(&mut self, key: PublicKey) {
+        self.authorized_keys.push(key);
+    }
+
+    /// Add a public key from a line in OpenSSH `authorized_keys` format.
+    ///
+    /// Leading options and a trailing comment are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::User;
+    /// let mut u = User::new("a", "12");
+    /// u.add_authorized_key_openssh(
+    ///     "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJdD7y3aLq454yWBdwLWbieU1ebz9/cu7/QEXn9OIeZJ a@host",
+    /// )
+    /// .unwrap();
+    /// assert_eq!(u.authorized_keys().len(), 1);
+    ///
+    /// assert!(u.add_authorized_key_openssh("not a key").is_err());
+    /// ```
+    pub fn add_authorized_key_openssh(&mut self, line: &str) -> Result<()> {
+        let mut words = line.split_whitespace();
+        while let Some(word) = words.next() {
+            if word.starts_with("ssh-") || word.starts_with("ecdsa-") {
+                let base64 = words
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing key data in line: {line}"))?;
+                let key = russh_keys::parse_public_key_base64(base64)?;
+                self.add_authorized_key(key);
+                return Ok(());
+            }
+        }
+        Err(anyhow!("Unknown key type in line: {line}"))
+    }
+
+    /// Get public keys allowed to log in as this user.
+    pub fn authorized_keys(&self) -> &[PublicKey] {
+        &self.authorized_keys
+    }
+
+    /// Set keyboard-interactive script used by this user.
+    ///
+    /// It overrides script set by [crate::SshServerBuilder::keyboard_interactive].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{KeyboardInteractive, User};
+    /// let mut u = User::new("a", "12");
+    /// u.set_keyboard_interactive(KeyboardInteractive::default().add_prompt("OTP: ", true, "42"));
+    /// assert!(u.keyboard_interactive().is_some());
+    /// ```
+    pub fn set_keyboard_interactive(&mut self, script: KeyboardInteractive) {
+        self.keyboard_interactive = Some(script);
+    }
+
+    /// Get keyboard-interactive script of this user.
+    pub fn keyboard_interactive(&self) -> Option<&KeyboardInteractive> {
+        self.keyboard_interactive.as_ref()
+    }
+
+    /// Require multi-factor authentication.
+    ///
+    /// Every element of the list is a single authentication step, the client
+    /// has to pass one of the methods from every step in the given order.
+    /// Until all steps are done the server rejects every passed step and
+    /// lists only methods of the next step. The partial success flag is
+    /// never set, so the client sees the same failure as after a wrong
+    /// password, only the list of methods changes.
+    ///
+    /// Empty list (default) means that any single method is enough.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_test_server::{MethodSet, User};
+    /// let mut u = User::new("a", "12");
+    /// u.set_required_auth_methods(&[MethodSet::PUBLICKEY, MethodSet::PASSWORD]);
+    /// assert_eq!(u.required_auth_methods().len(), 2);
+    /// ```
+    pub fn set_required_auth_methods(&mut self, steps: &[MethodSet]) {
+        self.required_auth_methods = steps.to_vec();
+    }
+
+    /// Get authentication steps required by this user.
+    pub fn required_auth_methods(&self) -> &[MethodSet] {
+        &self.required_auth_methods
+    }
 }
@@ -0,0 +1,572 @@
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Stop resolving symbolic links after so many hops.
+const MAX_SYMLINKS: usize = 40;
+
+const DEFAULT_FILE_PERMISSIONS: u32 = 0o644;
+const DEFAULT_DIR_PERMISSIONS: u32 = 0o755;
+const SYMLINK_PERMISSIONS: u32 = 0o777;
+
+/// Largest file which ssh clients can create, protects tests from
+/// clients asking for a huge offset or size.
+const MAX_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// In-memory filesystem served by the ssh server.
+///
+/// Paths are absolute or relative to the root directory `/`.
+/// Cloning is cheap and all clones share the same files, so tests can
+/// pre-populate the filesystem before the server starts and inspect it
+/// while clients are connected.
+///
+/// Methods of this type are not restricted by file permissions.
+/// Permissions are enforced only for ssh clients. Files written by ssh
+/// clients are limited to 256 MiB.
+///
+/// # Example
+///
+/// ```
+/// use ssh_test_server::MemoryFs;
+///
+/// let fs = MemoryFs::default();
+/// fs.write("/etc/motd", "Hello").unwrap();
+///
+/// assert_eq!(fs.read_to_string("/etc/motd").unwrap(), "Hello");
+/// assert_eq!(fs.read_dir("/etc").unwrap(), vec!["motd"]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFs {
+    tree: Arc<Mutex<Tree>>,
+}
+
+/// Type of the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    /// Regular file.
+    File,
+    /// Directory.
+    Dir,
+    /// Symbolic link.
+    Symlink,
+}
+
+/// Metadata of the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    /// Type of the file.
+    pub file_type: FileType,
+    /// Size of the file in bytes.
+    pub len: u64,
+    /// Permission bits, for example `0o644`.
+    pub permissions: u32,
+    /// Modification time in seconds since unix epoch.
+    pub modified: u32,
+}
+
+#[derive(Debug)]
+struct Node {
+    content: Content,
+    permissions: u32,
+    modified: u32,
+}
+
+#[derive(Debug)]
+enum Content {
+    File(Vec<u8>),
+    Dir,
+    Symlink(String),
+}
+
+impl Node {
+    fn new(content: Content, permissions: u32) -> Self {
+        Self {
+            content,
+            permissions,
+            modified: now(),
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        let (file_type, len) = match &self.content {
+            Content::File(data) => (FileType::File, data.len()),
+            Content::Dir => (FileType::Dir, 0),
+            Content::Symlink(target) => (FileType::Symlink, target.len()),
+        };
+        Metadata {
+            file_type,
+            len: len as u64,
+            permissions: self.permissions,
+            modified: self.modified,
+        }
+    }
+}
+
+/// Files indexed by normalized absolute path.
+#[derive(Debug)]
+struct Tree {
+    nodes: BTreeMap<String, Node>,
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        let root = Node::new(Content::Dir, DEFAULT_DIR_PERMISSIONS);
+        Self {
+            nodes: BTreeMap::from([("/".to_string(), root)]),
+        }
+    }
+}
+
+impl Tree {
+    /// Normalize path and resolve symbolic links.
+    ///
+    /// The last component is resolved only when `follow` is true.
+    /// Resolved path doesn't have to exist.
+    fn resolve(&self, path: &str, follow: bool) -> io::Result<String> {
+        let mut pending: Vec<String> = components(path).rev().map(str::to_string).collect();
+        let mut resolved: Vec<String> = vec![];
+        let mut links = 0;
+
+        while let Some(component) = pending.pop() {
+            if component == ".." {
+                resolved.pop();
+                continue;
+            }
+            resolved.push(component);
+
+            let Some(Node {
+                content: Content::Symlink(target),
+                ..
+            }) = self.nodes.get(&join(&resolved))
+            else {
+                continue;
+            };
+            if pending.is_empty() && !follow {
+                break;
+            }
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(error(
+                    ErrorKind::InvalidInput,
+                    "Too many levels of symbolic links",
+                ));
+            }
+            resolved.pop();
+            if target.starts_with('/') {
+                resolved.clear();
+            }
+            pending.extend(components(target).rev().map(str::to_string));
+        }
+
+        Ok(join(&resolved))
+    }
+
+    fn node(&self, path: &str, follow: bool) -> io::Result<(String, &Node)> {
+        let path = self.resolve(path, follow)?;
+        match self.nodes.get(&path) {
+            Some(node) => Ok((path, node)),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn node_mut(&mut self, path: &str, follow: bool) -> io::Result<&mut Node> {
+        let path = self.resolve(path, follow)?;
+        self.nodes.get_mut(&path).ok_or_else(|| not_found(&path))
+    }
+
+    /// Check that parent of normalized path is a directory.
+    fn check_parent(&self, path: &str) -> io::Result<()> {
+        let parent = parent(path);
+        match self.nodes.get(parent).map(|n| &n.content) {
+            Some(Content::Dir) => Ok(()),
+            Some(_) => Err(error(ErrorKind::InvalidInput, "Not a directory")),
+            None => Err(not_found(parent)),
+        }
+    }
+
+    fn insert(&mut self, path: String, node: Node) -> io::Result<()> {
+        self.check_parent(&path)?;
+        if self.nodes.contains_key(&path) {
+            return Err(error(ErrorKind::AlreadyExists, "File exists"));
+        }
+        self.nodes.insert(path, node);
+        Ok(())
+    }
+
+    /// Entries of normalized directory path.
+    fn children<'a>(&'a self, dir: &str) -> impl Iterator<Item = (&'a str, &'a Node)> {
+        let prefix = if dir == "/" {
+            dir.to_string()
+        } else {
+            format!("{dir}/")
+        };
+        let len = prefix.len();
+        self.nodes
+            .range(prefix.clone()..)
+            .take_while(move |(path, _)| path.starts_with(&prefix))
+            .map(move |(path, node)| (&path[len..], node))
+            .filter(|(name, _)| !name.is_empty() && !name.contains('/'))
+    }
+
+    fn create_dir_all(&mut self, path: &str) -> io::Result<()> {
+        let path = self.resolve(path, true)?;
+        let mut current = vec![];
+        for component in components(&path) {
+            current.push(component.to_string());
+            let current = join(&current);
+            match self.nodes.get(&current).map(|n| &n.content) {
+                Some(Content::Dir) => {}
+                Some(_) => return Err(error(ErrorKind::AlreadyExists, "File exists")),
+                None => {
+                    let dir = Node::new(Content::Dir, DEFAULT_DIR_PERMISSIONS);
+                    self.nodes.insert(current, dir);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MemoryFs {
+    /// Create an empty filesystem with only the root directory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write file, replacing its contents when it exists.
+    ///
+    /// Missing parent directories are created.
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let path = tree.resolve(path, true)?;
+        tree.create_dir_all(parent(&path))?;
+        match tree.nodes.get_mut(&path) {
+            Some(Node {
+                content: Content::File(data),
+                modified,
+                ..
+            }) => {
+                *data = contents.as_ref().to_vec();
+                *modified = now();
+                Ok(())
+            }
+            Some(_) => Err(error(ErrorKind::InvalidInput, "Is a directory")),
+            None => {
+                let file = Content::File(contents.as_ref().to_vec());
+                tree.insert(path, Node::new(file, DEFAULT_FILE_PERMISSIONS))
+            }
+        }
+    }
+
+    /// Read the whole file.
+    pub fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let tree = self.tree.lock().unwrap();
+        match tree.node(path, true)?.1 {
+            Node {
+                content: Content::File(data),
+                ..
+            } => Ok(data.clone()),
+            _ => Err(error(ErrorKind::InvalidInput, "Is a directory")),
+        }
+    }
+
+    /// Read the whole file as UTF-8 string.
+    pub fn read_to_string(&self, path: &str) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|_| error(ErrorKind::InvalidData, "File is not valid UTF-8"))
+    }
+
+    /// Names of entries in a directory, sorted.
+    pub fn read_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        Ok(self
+            .read_dir_metadata(path)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    /// Create a directory. Parent directory has to exist.
+    pub fn create_dir(&self, path: &str) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let path = tree.resolve(path, false)?;
+        tree.insert(path, Node::new(Content::Dir, DEFAULT_DIR_PERMISSIONS))
+    }
+
+    /// Create a directory and all missing parent directories.
+    pub fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        self.tree.lock().unwrap().create_dir_all(path)
+    }
+
+    /// Remove a file or a symbolic link.
+    pub fn remove_file(&self, path: &str) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let (path, node) = tree.node(path, false)?;
+        if matches!(node.content, Content::Dir) {
+            return Err(error(ErrorKind::InvalidInput, "Is a directory"));
+        }
+        tree.nodes.remove(&path);
+        Ok(())
+    }
+
+    /// Remove an empty directory.
+    pub fn remove_dir(&self, path: &str) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let (path, node) = tree.node(path, false)?;
+        if !matches!(node.content, Content::Dir) {
+            return Err(error(ErrorKind::InvalidInput, "Not a directory"));
+        }
+        if path == "/" || tree.children(&path).next().is_some() {
+            return Err(error(ErrorKind::Other, "Directory not empty"));
+        }
+        tree.nodes.remove(&path);
+        Ok(())
+    }
+
+    /// Rename a file or a directory. Existing target file is replaced.
+    ///
+    /// Unlike this method, SFTP clients get an error when the target exists.
+    pub fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let (from, _) = tree.node(from, false)?;
+        let to = tree.resolve(to, false)?;
+        if from == to {
+            return Ok(());
+        }
+        if from == "/" || to.starts_with(&format!("{from}/")) {
+            return Err(error(
+                ErrorKind::InvalidInput,
+                "Cannot move a directory into itself",
+            ));
+        }
+        tree.check_parent(&to)?;
+        match tree.nodes.get(&to).map(|n| &n.content) {
+            Some(Content::Dir) => return Err(error(ErrorKind::AlreadyExists, "File exists")),
+            Some(_) => {
+                tree.nodes.remove(&to);
+            }
+            None => {}
+        }
+
+        let prefix = format!("{from}/");
+        let moved: Vec<_> = tree
+            .nodes
+            .keys()
+            .filter(|p| **p == from || p.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for path in moved {
+            let node = tree.nodes.remove(&path).unwrap();
+            tree.nodes
+                .insert(format!("{to}{}", &path[from.len()..]), node);
+        }
+        Ok(())
+    }
+
+    /// Create a symbolic link at `link` pointing to `target`.
+    pub fn symlink(&self, target: &str, link: &str) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let link = tree.resolve(link, false)?;
+        let node = Node::new(Content::Symlink(target.to_string()), SYMLINK_PERMISSIONS);
+        tree.insert(link, node)
+    }
+
+    /// Read target of a symbolic link.
+    pub fn read_link(&self, path: &str) -> io::Result<String> {
+        let tree = self.tree.lock().unwrap();
+        match &tree.node(path, false)?.1.content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(error(ErrorKind::InvalidInput, "Not a symbolic link")),
+        }
+    }
+
+    /// Set permission bits of a file, for example `0o600`.
+    pub fn set_permissions(&self, path: &str, permissions: u32) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        tree.node_mut(path, true)?.permissions = permissions & 0o7777;
+        Ok(())
+    }
+
+    /// Metadata of a file. Symbolic links are followed.
+    pub fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        Ok(self.tree.lock().unwrap().node(path, true)?.1.metadata())
+    }
+
+    /// Metadata of a file. Symbolic links are not followed.
+    pub fn symlink_metadata(&self, path: &str) -> io::Result<Metadata> {
+        Ok(self.tree.lock().unwrap().node(path, false)?.1.metadata())
+    }
+
+    /// Returns true if the path points at an existing file.
+    pub fn exists(&self, path: &str) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    pub(crate) fn read_dir_metadata(&self, path: &str) -> io::Result<Vec<(String, Metadata)>> {
+        let tree = self.tree.lock().unwrap();
+        let (path, node) = tree.node(path, true)?;
+        if !matches!(node.content, Content::Dir) {
+            return Err(error(ErrorKind::InvalidInput, "Not a directory"));
+        }
+        Ok(tree
+            .children(&path)
+            .map(|(name, node)| (name.to_string(), node.metadata()))
+            .collect())
+    }
+
+    /// Normalized absolute path with resolved symbolic links.
+    pub(crate) fn canonicalize(&self, path: &str) -> io::Result<String> {
+        self.tree.lock().unwrap().resolve(path, true)
+    }
+
+    /// Check that all `mask` permission bits of the owner are set for the file.
+    pub(crate) fn check_access(&self, path: &str, mask: u32) -> io::Result<()> {
+        let permissions = self.metadata(path)?.permissions;
+        if permissions & mask != mask {
+            return Err(error(ErrorKind::PermissionDenied, "Permission denied"));
+        }
+        Ok(())
+    }
+
+    /// Check that an entry can be added to or removed from the parent directory.
+    pub(crate) fn check_parent_writable(&self, path: &str) -> io::Result<()> {
+        let path = self.tree.lock().unwrap().resolve(path, false)?;
+        self.check_access(parent(&path), 0o200)
+    }
+
+    /// Open a regular file and return its normalized path.
+    pub(crate) fn open(
+        &self,
+        path: &str,
+        create: bool,
+        truncate: bool,
+        exclusive: bool,
+        permissions: Option<u32>,
+    ) -> io::Result<String> {
+        let mut tree = self.tree.lock().unwrap();
+        let path = tree.resolve(path, true)?;
+        match tree.nodes.get_mut(&path) {
+            Some(_) if create && exclusive => Err(error(ErrorKind::AlreadyExists, "File exists")),
+            Some(Node {
+                content: Content::File(data),
+                modified,
+                ..
+            }) => {
+                if truncate {
+                    data.clear();
+                    *modified = now();
+                }
+                Ok(path)
+            }
+            Some(_) => Err(error(ErrorKind::InvalidInput, "Is a directory")),
+            None if create => {
+                let permissions = permissions.unwrap_or(DEFAULT_FILE_PERMISSIONS) & 0o7777;
+                tree.insert(path.clone(), Node::new(Content::File(vec![]), permissions))?;
+                Ok(path)
+            }
+            None => Err(not_found(&path)),
+        }
+    }
+
+    pub(crate) fn read_at(&self, path: &str, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let tree = self.tree.lock().unwrap();
+        match &tree.node(path, true)?.1.content {
+            Content::File(data) => {
+                let start = usize::try_from(offset)
+                    .unwrap_or(usize::MAX)
+                    .min(data.len());
+                let end = start.saturating_add(len).min(data.len());
+                Ok(data[start..end].to_vec())
+            }
+            _ => Err(error(ErrorKind::InvalidInput, "Is a directory")),
+        }
+    }
+
+    /// Write data at offset. Returns the new file size.
+    pub(crate) fn write_at(&self, path: &str, offset: u64, buf: &[u8]) -> io::Result<u64> {
+        let mut tree = self.tree.lock().unwrap();
+        match tree.node_mut(path, true)? {
+            Node {
+                content: Content::File(data),
+                modified,
+                ..
+            } => {
+                let end = offset
+                    .checked_add(buf.len() as u64)
+                    .filter(|end| *end <= MAX_FILE_SIZE)
+                    .ok_or_else(too_large)?;
+                let (start, end) = (offset as usize, end as usize);
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[start..end].copy_from_slice(buf);
+                *modified = now();
+                Ok(data.len() as u64)
+            }
+            _ => Err(error(ErrorKind::InvalidInput, "Is a directory")),
+        }
+    }
+
+    pub(crate) fn set_len(&self, path: &str, len: u64) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        match tree.node_mut(path, true)? {
+            Node {
+                content: Content::File(data),
+                modified,
+                ..
+            } => {
+                if len > MAX_FILE_SIZE {
+                    return Err(too_large());
+                }
+                data.resize(len as usize, 0);
+                *modified = now();
+                Ok(())
+            }
+            _ => Err(error(ErrorKind::InvalidInput, "Is a directory")),
+        }
+    }
+
+    pub(crate) fn set_modified(&self, path: &str, modified: u32) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        tree.node_mut(path, true)?.modified = modified;
+        Ok(())
+    }
+}
+
+fn components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".")
+}
+
+fn join(components: &[String]) -> String {
+    format!("/{}", components.join("/"))
+}
+
+/// Parent of normalized path.
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+fn error(kind: ErrorKind, msg: &str) -> io::Error {
+    io::Error::new(kind, msg.to_string())
+}
+
+fn too_large() -> io::Error {
+    error(ErrorKind::InvalidInput, "File too large")
+}
+
+fn not_found(path: &str) -> io::Error {
+    error(
+        ErrorKind::NotFound,
+        &format!("{path}: No such file or directory"),
+    )
+}
@@ -0,0 +1,181 @@
+use russh::{cipher, client, kex, mac};
+use ssh_test_server::{SshServer, SshServerBuilder, User};
+use std::borrow::Cow;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+#[tokio::test]
+async fn test_negotiated_algorithms_reported() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .kex_algorithms(&["diffie-hellman-group14-sha256"])
+        .cipher_algorithms(&["aes128-ctr"])
+        .mac_algorithms(&["hmac-sha2-256"])
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    let (stdout, _, _) = common::exec(&client, "echo abc").await;
+    assert_eq!(stdout.trim(), "abc");
+
+    let negotiated = server.negotiated_algorithms();
+    assert_eq!(negotiated.len(), 1);
+    let negotiated = &negotiated[0];
+    assert_eq!(negotiated.connection_id, 0);
+    assert_eq!(negotiated.kex, "diffie-hellman-group14-sha256");
+    assert_eq!(negotiated.host_key, "ssh-ed25519");
+    assert_eq!(negotiated.cipher, "aes128-ctr");
+    assert_eq!(negotiated.mac_client_to_server, "hmac-sha2-256");
+    assert_eq!(negotiated.mac_server_to_client, "hmac-sha2-256");
+    assert_eq!(negotiated.compression_client_to_server, "none");
+    assert_eq!(negotiated.compression_server_to_client, "none");
+}
+
+#[tokio::test]
+async fn test_reordered_ciphers_follow_client_preference() {
+    let server = SshServerBuilder::default()
+        .cipher_algorithms(&["aes128-ctr", "aes256-gcm@openssh.com"])
+        .run()
+        .await
+        .unwrap();
+
+    let mut config = client::Config::default();
+    config.preferred.cipher = Cow::Borrowed(&[cipher::AES_256_GCM, cipher::AES_128_CTR]);
+    client::connect(Arc::new(config), server.addr(), common::TestClient)
+        .await
+        .unwrap();
+
+    let negotiated = server.negotiated_algorithms();
+    assert_eq!(negotiated[0].cipher, "aes256-gcm@openssh.com");
+}
+
+#[tokio::test]
+async fn test_client_without_common_cipher_fails() {
+    let server = SshServerBuilder::default()
+        .cipher_algorithms(&["aes128-ctr"])
+        .run()
+        .await
+        .unwrap();
+
+    let mut config = client::Config::default();
+    config.preferred.cipher = Cow::Borrowed(&[cipher::CHACHA20_POLY1305]);
+    let result = client::connect(Arc::new(config), server.addr(), common::TestClient).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_client_without_common_kex_fails() {
+    let server = SshServerBuilder::default()
+        .kex_algorithms(&["diffie-hellman-group16-sha512"])
+        .run()
+        .await
+        .unwrap();
+
+    let mut config = client::Config::default();
+    config.preferred.kex = Cow::Borrowed(&[kex::CURVE25519]);
+    let result = client::connect(Arc::new(config), server.addr(), common::TestClient).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_client_without_common_mac_fails() {
+    let server = SshServerBuilder::default()
+        .cipher_algorithms(&["aes256-ctr"])
+        .mac_algorithms(&["hmac-sha2-512"])
+        .run()
+        .await
+        .unwrap();
+
+    let mut config = client::Config::default();
+    config.preferred.mac = Cow::Borrowed(&[mac::HMAC_SHA1]);
+    let result = client::connect(Arc::new(config), server.addr(), common::TestClient).await;
+    assert!(result.is_err());
+    assert!(server.negotiated_algorithms().is_empty());
+}
+
+#[tokio::test]
+async fn test_unsupported_algorithm_name() {
+    let result = SshServerBuilder::default()
+        .cipher_algorithms(&["rot13"])
+        .run()
+        .await;
+    assert!(result.is_err());
+}
+
+/// Connect through a proxy which ends the client's identification line with
+/// a bare LF, like OpenSSH 7.4 does.
+async fn connect_with_lf_id(
+    server: &SshServer,
+    config: client::Config,
+) -> client::Handle<common::TestClient> {
+    let (client_stream, proxy) = tokio::io::duplex(64 * 1024);
+    let (proxy_read, mut proxy_write) = tokio::io::split(proxy);
+    let (mut server_read, mut server_write) = tokio::io::split(server.connect_in_memory());
+    tokio::spawn(async move { tokio::io::copy(&mut server_read, &mut proxy_write).await });
+    tokio::spawn(async move {
+        let mut proxy_read = BufReader::new(proxy_read);
+        let mut id = vec![];
+        proxy_read.read_until(b'\n', &mut id).await.unwrap();
+        id.truncate(id.len() - 2);
+        id.push(b'\n');
+        server_write.write_all(&id).await.unwrap();
+        tokio::io::copy(&mut proxy_read, &mut server_write).await
+    });
+    client::connect_stream(Arc::new(config), client_stream, common::TestClient)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_identification_line_ending_with_lf() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+
+    let mut config = client::Config::default();
+    config.preferred.cipher = Cow::Borrowed(&[cipher::AES_256_CTR]);
+    config.preferred.mac = Cow::Borrowed(&[mac::HMAC_SHA512]);
+    let mut client = connect_with_lf_id(&server, config).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+
+    let negotiated = server.negotiated_algorithms();
+    assert_eq!(negotiated.len(), 1);
+    assert_eq!(negotiated[0].cipher, "aes256-ctr");
+    assert_eq!(negotiated[0].mac_client_to_server, "hmac-sha2-512");
+    assert_eq!(negotiated[0].mac_server_to_client, "hmac-sha2-512");
+}
+
+#[tokio::test]
+async fn test_aead_cipher_without_common_mac() {
+    let server = SshServerBuilder::default()
+        .cipher_algorithms(&["chacha20-poly1305@openssh.com"])
+        .mac_algorithms(&["hmac-sha2-512"])
+        .run()
+        .await
+        .unwrap();
+
+    let mut config = client::Config::default();
+    config.preferred.mac = Cow::Borrowed(&[mac::HMAC_SHA1]);
+    client::connect(Arc::new(config), server.addr(), common::TestClient)
+        .await
+        .unwrap();
+
+    let negotiated = &server.negotiated_algorithms()[0];
+    assert_eq!(negotiated.cipher, "chacha20-poly1305@openssh.com");
+    assert_eq!(negotiated.mac_client_to_server, "none");
+    assert_eq!(negotiated.mac_server_to_client, "none");
+}
@@ -0,0 +1,78 @@
+use russh::Disconnect;
+use ssh_test_server::{AuthOutcome, MethodSet, SshServerBuilder, User};
+use std::time::Duration;
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+#[tokio::test]
+async fn test_audit_log() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    assert!(!client
+        .authenticate_password(USER_LOGIN, "wrong")
+        .await
+        .unwrap());
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    common::exec(&client, "echo abc").await;
+    common::exec(&client, "x_echo").await;
+    client
+        .disconnect(Disconnect::ByApplication, "", "")
+        .await
+        .unwrap();
+
+    let mut log = server.audit_log();
+    for _ in 0..50 {
+        if log.connections[0].disconnected_at.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        log = server.audit_log();
+    }
+
+    assert_eq!(log.connections.len(), 1);
+    let connection = &log.connections[0];
+    assert_eq!(connection.connection_id, 0);
+    assert_eq!(connection.user.as_deref(), Some(USER_LOGIN));
+    assert!(connection.peer_addr.unwrap().ip().is_loopback());
+    assert!(connection.disconnected_at.unwrap() >= connection.connected_at);
+
+    let attempts: Vec<_> = log
+        .auth_attempts
+        .iter()
+        .map(|a| (a.connection_id, a.user.as_str(), a.method, a.outcome))
+        .collect();
+    assert_eq!(
+        attempts,
+        [
+            (0, USER_LOGIN, MethodSet::PASSWORD, AuthOutcome::Rejected),
+            (0, USER_LOGIN, MethodSet::PASSWORD, AuthOutcome::Accepted),
+        ]
+    );
+
+    let commands: Vec<_> = log
+        .commands
+        .iter()
+        .map(|c| (c.user.as_str(), c.command.as_str(), c.shell, c.exit_code))
+        .collect();
+    assert_eq!(
+        commands,
+        [
+            (USER_LOGIN, "echo abc", false, Some(0)),
+            (USER_LOGIN, "x_echo", false, Some(127)),
+        ]
+    );
+    for command in &log.commands {
+        assert!(command.finished_at.unwrap() >= command.started_at);
+        assert!(command.started_at >= connection.connected_at);
+    }
+}
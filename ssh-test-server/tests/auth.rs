@@ -0,0 +1,289 @@
+use russh::client::KeyboardInteractiveAuthResponse;
+use russh_keys::key::KeyPair;
+use russh_keys::PublicKeyBase64;
+use ssh_test_server::{KeyboardInteractive, MethodSet, SshServerBuilder, User};
+use std::sync::Arc;
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+#[tokio::test]
+async fn test_publickey_accepted() {
+    let key = KeyPair::generate_ed25519();
+    let mut user = User::new(USER_LOGIN, USER_PASS);
+    user.add_authorized_key(key.clone_public_key().unwrap());
+
+    let server = SshServerBuilder::default()
+        .add_user(user)
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    let authenticated = client
+        .authenticate_publickey(USER_LOGIN, Arc::new(key))
+        .await
+        .unwrap();
+    assert!(authenticated);
+
+    let (stdout, _, status_code) = common::exec(&client, "echo abc").await;
+    assert_eq!(status_code, 0);
+    assert_eq!(stdout.trim(), "abc");
+}
+
+#[tokio::test]
+async fn test_publickey_from_authorized_keys_line() {
+    let key = KeyPair::generate_ed25519();
+    let line = format!(
+        "no-pty {} {} test@host",
+        key.name(),
+        key.public_key_base64()
+    );
+    let mut user = User::new(USER_LOGIN, USER_PASS);
+    user.add_authorized_key_openssh(&line).unwrap();
+
+    let server = SshServerBuilder::default()
+        .add_user(user)
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    let authenticated = client
+        .authenticate_publickey(USER_LOGIN, Arc::new(key))
+        .await
+        .unwrap();
+    assert!(authenticated);
+}
+
+#[tokio::test]
+async fn test_publickey_unknown_key_rejected() {
+    let mut user = User::new(USER_LOGIN, USER_PASS);
+    user.add_authorized_key(KeyPair::generate_ed25519().clone_public_key().unwrap());
+
+    let server = SshServerBuilder::default()
+        .add_user(user)
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    let authenticated = client
+        .authenticate_publickey(USER_LOGIN, Arc::new(KeyPair::generate_ed25519()))
+        .await
+        .unwrap();
+    assert!(!authenticated);
+}
+
+#[tokio::test]
+async fn test_keyboard_interactive_global_script() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .keyboard_interactive(
+            KeyboardInteractive::default()
+                .add_prompt("Password: ", false, USER_PASS)
+                .next_round()
+                .add_prompt("OTP: ", true, "123456"),
+        )
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    let response = client
+        .authenticate_keyboard_interactive_start(USER_LOGIN, None)
+        .await
+        .unwrap();
+    let KeyboardInteractiveAuthResponse::InfoRequest { prompts, .. } = response else {
+        panic!("expected info request, got {response:?}");
+    };
+    assert_eq!(prompts.len(), 1);
+    assert_eq!(prompts[0].prompt, "Password: ");
+    assert!(!prompts[0].echo);
+
+    let response = client
+        .authenticate_keyboard_interactive_respond(vec![USER_PASS.to_string()])
+        .await
+        .unwrap();
+    let KeyboardInteractiveAuthResponse::InfoRequest { prompts, .. } = response else {
+        panic!("expected info request, got {response:?}");
+    };
+    assert_eq!(prompts[0].prompt, "OTP: ");
+    assert!(prompts[0].echo);
+
+    let response = client
+        .authenticate_keyboard_interactive_respond(vec!["123456".to_string()])
+        .await
+        .unwrap();
+    assert!(matches!(response, KeyboardInteractiveAuthResponse::Success));
+}
+
+#[tokio::test]
+async fn test_keyboard_interactive_per_user_script() {
+    let mut user = User::new(USER_LOGIN, USER_PASS);
+    user.set_keyboard_interactive(
+        KeyboardInteractive::default()
+            .add_prompt("PIN: ", false, "0000")
+            .add_prompt("Token: ", false, "abc"),
+    );
+
+    let server = SshServerBuilder::default()
+        .add_user(user)
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    let response = client
+        .authenticate_keyboard_interactive_start(USER_LOGIN, None)
+        .await
+        .unwrap();
+    let KeyboardInteractiveAuthResponse::InfoRequest { prompts, .. } = response else {
+        panic!("expected info request, got {response:?}");
+    };
+    assert_eq!(prompts.len(), 2);
+
+    let response = client
+        .authenticate_keyboard_interactive_respond(vec!["0000".to_string(), "abc".to_string()])
+        .await
+        .unwrap();
+    assert!(matches!(response, KeyboardInteractiveAuthResponse::Success));
+
+    let (stdout, _, status_code) = common::exec(&client, "echo abc").await;
+    assert_eq!(status_code, 0);
+    assert_eq!(stdout.trim(), "abc");
+}
+
+#[tokio::test]
+async fn test_keyboard_interactive_wrong_answer_rejected() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .keyboard_interactive(KeyboardInteractive::default().add_prompt("OTP: ", true, "123456"))
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    client
+        .authenticate_keyboard_interactive_start(USER_LOGIN, None)
+        .await
+        .unwrap();
+    let response = client
+        .authenticate_keyboard_interactive_respond(vec!["654321".to_string()])
+        .await
+        .unwrap();
+    assert!(matches!(response, KeyboardInteractiveAuthResponse::Failure));
+}
+
+#[tokio::test]
+async fn test_multi_factor_publickey_then_password() {
+    let key = KeyPair::generate_ed25519();
+    let mut user = User::new(USER_LOGIN, USER_PASS);
+    user.add_authorized_key(key.clone_public_key().unwrap());
+    user.set_required_auth_methods(&[MethodSet::PUBLICKEY, MethodSet::PASSWORD]);
+
+    let server = SshServerBuilder::default()
+        .add_user(user)
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    let key = Arc::new(key);
+
+    // Password is not accepted as the first factor.
+    let authenticated = client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap();
+    assert!(!authenticated);
+
+    let authenticated = client
+        .authenticate_publickey(USER_LOGIN, key)
+        .await
+        .unwrap();
+    assert!(!authenticated, "publickey alone must not be enough");
+
+    let authenticated = client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap();
+    assert!(authenticated);
+
+    let (stdout, _, status_code) = common::exec(&client, "echo abc").await;
+    assert_eq!(status_code, 0);
+    assert_eq!(stdout.trim(), "abc");
+}
+
+#[tokio::test]
+async fn test_multi_factor_keyboard_interactive_then_password() {
+    let mut user = User::new(USER_LOGIN, USER_PASS);
+    user.set_keyboard_interactive(KeyboardInteractive::default().add_prompt("OTP: ", true, "42"));
+    user.set_required_auth_methods(&[MethodSet::KEYBOARD_INTERACTIVE, MethodSet::PASSWORD]);
+
+    let server = SshServerBuilder::default()
+        .add_user(user)
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    let response = client
+        .authenticate_keyboard_interactive_start(USER_LOGIN, None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        response,
+        KeyboardInteractiveAuthResponse::InfoRequest { .. }
+    ));
+    let response = client
+        .authenticate_keyboard_interactive_respond(vec!["42".to_string()])
+        .await
+        .unwrap();
+    assert!(matches!(response, KeyboardInteractiveAuthResponse::Failure));
+
+    let authenticated = client
+        .authenticate_password(USER_LOGIN, "wrong")
+        .await
+        .unwrap();
+    assert!(!authenticated);
+
+    let authenticated = client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap();
+    assert!(authenticated);
+}
+
+#[tokio::test]
+async fn test_multi_factor_methods_seen_by_client() {
+    let mut user = User::new(USER_LOGIN, USER_PASS);
+    user.set_keyboard_interactive(KeyboardInteractive::default().add_prompt("OTP: ", true, "42"));
+    user.set_required_auth_methods(&[MethodSet::PASSWORD, MethodSet::KEYBOARD_INTERACTIVE]);
+
+    let server = SshServerBuilder::default()
+        .add_user(user)
+        .run()
+        .await
+        .unwrap();
+
+    let addr = server.addr();
+    tokio::task::spawn_blocking(move || {
+        let mut sess = ssh2::Session::new().unwrap();
+        sess.set_tcp_stream(std::net::TcpStream::connect(addr).unwrap());
+        sess.handshake().unwrap();
+        sess.set_timeout(5000);
+
+        assert_eq!(sess.auth_methods(USER_LOGIN).unwrap(), "password");
+        // Passed step looks like a failure, only the methods change.
+        assert!(sess.userauth_password(USER_LOGIN, USER_PASS).is_err());
+        assert!(!sess.authenticated());
+        assert_eq!(
+            sess.auth_methods(USER_LOGIN).unwrap(),
+            "keyboard-interactive"
+        );
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,59 @@
+use ssh2::Session;
+use ssh_test_server::{ServerEvent, SshServerBuilder, User};
+use std::io::Read;
+use std::net::TcpStream;
+use std::time::Duration;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+fn exec(addr: &str, command: &str) -> (String, i32) {
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(TcpStream::connect(addr).unwrap());
+    sess.handshake().unwrap();
+    sess.set_timeout(5000);
+    sess.userauth_password(USER_LOGIN, USER_PASS).unwrap();
+
+    let mut channel = sess.channel_session().unwrap();
+    channel.exec(command).unwrap();
+    let mut stdout = String::new();
+    channel.read_to_string(&mut stdout).unwrap();
+    channel.wait_close().unwrap();
+    (stdout, channel.exit_status().unwrap())
+}
+
+#[test]
+fn test_run_blocking() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run_blocking()
+        .unwrap();
+
+    assert_eq!(exec(&server.addr(), "echo abc"), ("abc\n".to_string(), 0));
+    server
+        .wait_for(
+            |e| matches!(e, ServerEvent::CommandFinished { .. }),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+    assert_eq!(server.audit_log().commands.len(), 1);
+    assert!(server.users().lock().unwrap().contains_key(USER_LOGIN));
+
+    server.stop();
+    server.restart().unwrap();
+    assert_eq!(exec(&server.addr(), "echo abc").1, 0);
+
+    let addr = server.addr();
+    drop(server);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn test_run_blocking_error() {
+    let first = SshServerBuilder::default().run_blocking().unwrap();
+    let error = SshServerBuilder::default()
+        .port(first.port())
+        .run_blocking()
+        .unwrap_err();
+    assert!(error.to_string().contains("already in use"));
+}
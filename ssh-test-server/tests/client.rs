@@ -0,0 +1,144 @@
+use russh_keys::key::KeyPair;
+use ssh_test_server::{MethodSet, SshServer, SshServerBuilder, User};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn run_server() -> SshServer {
+    SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_tcp_endpoint("db.internal", 5432, |mut stream| async move {
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        })
+        .run()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_exec() {
+    let server = run_server().await;
+    let client = server.client(USER_LOGIN).await.unwrap();
+
+    let output = client.exec("echo abc").await.unwrap();
+    assert_eq!(output.stdout, b"abc\n");
+    assert_eq!(output.status, Some(0));
+
+    let output = client.exec("nocmd").await.unwrap();
+    assert_eq!(output.stderr, b"nocmd: command not found\n");
+    assert_eq!(output.status, Some(127));
+
+    assert_eq!(
+        server.audit_log().connections[0].user.as_deref(),
+        Some(USER_LOGIN)
+    );
+    assert!(server.client("nobody").await.is_err());
+}
+
+#[tokio::test]
+async fn test_shell() {
+    let server = run_server().await;
+    let client = server.client(USER_LOGIN).await.unwrap();
+
+    let mut shell = client.shell().await.unwrap();
+    shell.expect(r"\$ ", TIMEOUT).await.unwrap();
+    shell.send_line("echo first").await.unwrap();
+    shell.send_line("echo second").await.unwrap();
+    let output = shell.expect(r"\r\nfirst\r\n", TIMEOUT).await.unwrap();
+    assert!(output.starts_with("echo first"));
+    shell.expect(r"\r\nsecond\r\n", TIMEOUT).await.unwrap();
+
+    let error = shell
+        .expect("third", Duration::from_millis(100))
+        .await
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("Timed out waiting for `third`"));
+}
+
+#[tokio::test]
+async fn test_sftp() {
+    let server = run_server().await;
+    let client = server.client(USER_LOGIN).await.unwrap();
+
+    let sftp = client.sftp().await.unwrap();
+    let mut file = sftp.create("/data.txt").await.unwrap();
+    file.write_all(b"content").await.unwrap();
+    file.shutdown().await.unwrap();
+    assert_eq!(server.fs().read("/data.txt").unwrap(), b"content");
+}
+
+#[tokio::test]
+async fn test_port_forwarding() {
+    let server = run_server().await;
+    let mut client = server.client(USER_LOGIN).await.unwrap();
+
+    let mut stream = client.forward_local("db.internal", 5432).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    let port = client.request_remote_forward("127.0.0.1", 0).await.unwrap();
+    let mut server_stream = server
+        .open_remote_forward("127.0.0.1", port, "10.0.0.1", 50000)
+        .await
+        .unwrap();
+    let mut forwarded = client.accept_forwarded(TIMEOUT).await.unwrap();
+    assert_eq!(forwarded.connected_port, port);
+    assert_eq!(forwarded.originator_address, "10.0.0.1");
+
+    server_stream.write_all(b"pong").await.unwrap();
+    forwarded.stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+}
+
+#[tokio::test]
+async fn test_client_with_key() {
+    let key = KeyPair::generate_ed25519();
+    let mut user = User::new(USER_LOGIN, USER_PASS);
+    user.add_authorized_key(key.clone_public_key().unwrap());
+    user.set_required_auth_methods(&[MethodSet::PUBLICKEY]);
+    let server = SshServerBuilder::default()
+        .add_user(user)
+        .run()
+        .await
+        .unwrap();
+
+    let client = server.client_with_key(USER_LOGIN, key).await.unwrap();
+    assert_eq!(client.exec("echo abc").await.unwrap().stdout, b"abc\n");
+
+    let error = server.client(USER_LOGIN).await.unwrap_err();
+    assert!(error.to_string().contains("use SshServer::client_with_key"));
+    let error = server
+        .client_with_key(USER_LOGIN, KeyPair::generate_ed25519())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Public key authentication of user1 failed, the key is not authorized"
+    );
+}
+
+#[tokio::test]
+async fn test_client_multi_factor_not_supported() {
+    let mut user = User::new(USER_LOGIN, USER_PASS);
+    user.set_required_auth_methods(&[MethodSet::PUBLICKEY, MethodSet::PASSWORD]);
+    let server = SshServerBuilder::default()
+        .add_user(user)
+        .run()
+        .await
+        .unwrap();
+
+    let error = server.client(USER_LOGIN).await.unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "User user1 requires multiple authentication steps, which the client doesn't support"
+    );
+}
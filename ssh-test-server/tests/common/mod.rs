@@ -1,8 +1,64 @@
 #![allow(unused)]
+use async_trait::async_trait;
+use russh::client;
+use russh::ChannelMsg;
+use russh_keys::key::PublicKey;
+use russh_sftp::client::SftpSession;
 use ssh2::{Channel, Session};
 use std::io::{Read, Write};
+use std::sync::Arc;
 use tracing::info;
 
+/// Russh client handler that trusts any server key.
+pub struct TestClient;
+
+#[async_trait]
+impl client::Handler for TestClient {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, _key: &PublicKey) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+pub async fn connect(addr: &str) -> client::Handle<TestClient> {
+    let config = Arc::new(client::Config::default());
+    client::connect(config, addr, TestClient).await.unwrap()
+}
+
+pub async fn exec(handle: &client::Handle<TestClient>, command: &str) -> (String, String, u32) {
+    let channel = handle.channel_open_session().await.unwrap();
+    channel.exec(true, command).await.unwrap();
+    let (stdout, stderr, status_code) = output(channel).await;
+    (
+        String::from_utf8(stdout).unwrap(),
+        String::from_utf8(stderr).unwrap(),
+        status_code,
+    )
+}
+
+/// Collect raw output and exit status of the channel until it's closed.
+pub async fn output(mut channel: russh::Channel<client::Msg>) -> (Vec<u8>, Vec<u8>, u32) {
+    let mut stdout = vec![];
+    let mut stderr = vec![];
+    let mut status_code = None;
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } => stdout.extend_from_slice(&data),
+            ChannelMsg::ExtendedData { data, ext: 1 } => stderr.extend_from_slice(&data),
+            ChannelMsg::ExitStatus { exit_status } => status_code = Some(exit_status),
+            _ => {}
+        }
+    }
+    (stdout, stderr, status_code.unwrap())
+}
+
+pub async fn sftp(handle: &client::Handle<TestClient>) -> SftpSession {
+    let channel = handle.channel_open_session().await.unwrap();
+    channel.request_subsystem(true, "sftp").await.unwrap();
+    SftpSession::new(channel.into_stream()).await.unwrap()
+}
+
 pub async fn run_ssh_command<F>(
     addr: &str,
     username: &str,
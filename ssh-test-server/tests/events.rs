@@ -0,0 +1,239 @@
+use russh::client::KeyboardInteractiveAuthResponse;
+use russh::Disconnect;
+use ssh_test_server::{KeyboardInteractive, MethodSet, ServerEvent, SshServerBuilder, User};
+use std::time::Duration;
+use tokio::sync::oneshot;
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+#[tokio::test]
+async fn test_subscribe_events() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+    let mut events = server.subscribe();
+
+    let mut client = common::connect(&server.addr()).await;
+    assert!(!client
+        .authenticate_password(USER_LOGIN, "bad")
+        .await
+        .unwrap());
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    let channel = client.channel_open_session().await.unwrap();
+    channel
+        .request_pty(true, "xterm", 80, 24, 0, 0, &[])
+        .await
+        .unwrap();
+    channel.exec(true, "echo abc").await.unwrap();
+    common::output(channel).await;
+    client
+        .disconnect(Disconnect::ByApplication, "", "")
+        .await
+        .unwrap();
+
+    let mut received = vec![];
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        received.push(event.clone());
+        if matches!(event, ServerEvent::Disconnected { .. }) {
+            break;
+        }
+    }
+
+    let user = USER_LOGIN.to_string();
+    let method = MethodSet::PASSWORD;
+    assert!(matches!(
+        received[0],
+        ServerEvent::Connected {
+            connection_id: 0,
+            peer_addr: Some(_)
+        }
+    ));
+    assert_eq!(
+        received[1..],
+        [
+            ServerEvent::AuthAttempted {
+                connection_id: 0,
+                user: user.clone(),
+                method,
+            },
+            ServerEvent::AuthFailed {
+                connection_id: 0,
+                user: user.clone(),
+                method,
+                step_passed: false,
+            },
+            ServerEvent::AuthAttempted {
+                connection_id: 0,
+                user: user.clone(),
+                method,
+            },
+            ServerEvent::AuthSucceeded {
+                connection_id: 0,
+                user: user.clone(),
+                method,
+            },
+            ServerEvent::ChannelOpened {
+                connection_id: 0,
+                channel_type: "session".to_string(),
+            },
+            ServerEvent::PtyRequested {
+                connection_id: 0,
+                term: "xterm".to_string(),
+                col_width: 80,
+                row_height: 24,
+            },
+            ServerEvent::CommandStarted {
+                connection_id: 0,
+                user: user.clone(),
+                command: "echo abc".to_string(),
+            },
+            ServerEvent::CommandFinished {
+                connection_id: 0,
+                user: user.clone(),
+                command: "echo abc".to_string(),
+                exit_code: 0,
+            },
+            ServerEvent::Disconnected { connection_id: 0 },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_wait_for_event() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+
+    let addr = server.addr();
+    let (disconnect, disconnect_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let mut client = common::connect(&addr).await;
+        assert!(client
+            .authenticate_password(USER_LOGIN, USER_PASS)
+            .await
+            .unwrap());
+        let _ = disconnect_rx.await;
+    });
+
+    let event = server
+        .wait_for(
+            |e| matches!(e, ServerEvent::AuthSucceeded { .. }),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(event, ServerEvent::AuthSucceeded { user, .. } if user == USER_LOGIN));
+
+    // Past events are found too.
+    server
+        .wait_for(
+            |e| matches!(e, ServerEvent::Connected { .. }),
+            Duration::from_millis(10),
+        )
+        .await
+        .unwrap();
+
+    disconnect.send(()).unwrap();
+    server
+        .wait_for(
+            |e| matches!(e, ServerEvent::Disconnected { .. }),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+    let error = server
+        .wait_for(
+            |e| matches!(e, ServerEvent::CommandStarted { .. }),
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Timed out waiting for server event");
+}
+
+#[tokio::test]
+async fn test_wait_for_next_event() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+    let is_login = |e: &ServerEvent| matches!(e, ServerEvent::AuthSucceeded { .. });
+
+    let mut first = common::connect(&server.addr()).await;
+    assert!(first
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    server
+        .wait_for_next(is_login, Duration::from_millis(100))
+        .await
+        .unwrap_err();
+
+    let next_login = server.wait_for_next(is_login, Duration::from_secs(5));
+    let mut second = common::connect(&server.addr()).await;
+    assert!(second
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    let event = next_login.await.unwrap();
+    assert!(matches!(
+        event,
+        ServerEvent::AuthSucceeded {
+            connection_id: 1,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_auth_attempted_before_outcome() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .keyboard_interactive(KeyboardInteractive::default().add_prompt("OTP: ", true, "42"))
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    let response = client
+        .authenticate_keyboard_interactive_start(USER_LOGIN, None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        response,
+        KeyboardInteractiveAuthResponse::InfoRequest { .. }
+    ));
+
+    // Client didn't answer the prompt yet.
+    server
+        .wait_for(
+            |e| {
+                matches!(
+                    e,
+                    ServerEvent::AuthAttempted {
+                        method: MethodSet::KEYBOARD_INTERACTIVE,
+                        ..
+                    }
+                )
+            },
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+    assert!(server.audit_log().auth_attempts.is_empty());
+}
@@ -0,0 +1,85 @@
+use ssh_test_server::{SshExecuteResult, SshServer, SshServerBuilder, User};
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+const ROOT_LOGIN: &str = "root";
+const ROOT_PASS: &str = "root1234";
+
+async fn run_server() -> SshServer {
+    SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_user(User::new_admin(ROOT_LOGIN, ROOT_PASS))
+        .run()
+        .await
+        .unwrap()
+}
+
+async fn exec(server: &SshServer, login: &str, password: &str, command: &str) -> (String, u32) {
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client.authenticate_password(login, password).await.unwrap());
+    let (stdout, _, status_code) = common::exec(&client, command).await;
+    (stdout, status_code)
+}
+
+#[tokio::test]
+async fn test_verify_expected_commands() {
+    let server = run_server().await;
+    server.expect_command("systemctl stop *").times(2);
+    server.expect_command("reboot").returning(|context, _, _| {
+        SshExecuteResult::stdout(0, format!("rebooted by {}\n", context.current_user))
+    });
+
+    let output = exec(&server, ROOT_LOGIN, ROOT_PASS, "systemctl stop nginx").await;
+    assert_eq!(output.1, 127);
+    exec(&server, ROOT_LOGIN, ROOT_PASS, "systemctl stop mysql").await;
+    let output = exec(&server, ROOT_LOGIN, ROOT_PASS, "reboot").await;
+    assert_eq!(output, ("rebooted by root\n".to_string(), 0));
+
+    server.verify().unwrap();
+}
+
+#[tokio::test]
+async fn test_verify_reports_failures() {
+    let server = run_server().await;
+    server.expect_command("apt-get update");
+    server.expect_command("apt-get upgrade -y");
+    server.expect_command("reboot").times(1);
+
+    exec(&server, USER_LOGIN, USER_PASS, "apt-get upgrade -y").await;
+    exec(&server, ROOT_LOGIN, ROOT_PASS, "apt-get update").await;
+    exec(&server, USER_LOGIN, USER_PASS, "rm -rf /").await;
+    exec(&server, ROOT_LOGIN, ROOT_PASS, "apt-get update").await;
+
+    let error = server.verify().unwrap_err().to_string();
+    assert_eq!(
+        error,
+        "Ssh command expectations not met:\n\
+         \x20 out of order command `apt-get upgrade -y` run by user1: expected after `apt-get update`\n\
+         \x20 unexpected command `rm -rf /` run by user1\n\
+         \x20 unexpected command `apt-get update` run by root: `apt-get update` expected 1 times\n\
+         \x20 missing command `reboot`: expected 1 times, run 0 times\n"
+    );
+}
+
+#[tokio::test]
+async fn test_expectations_from_builder() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .expect_command("deploy *", |e| {
+            e.times(2)
+                .returning(|_, _, args| SshExecuteResult::stdout(0, format!("{}\n", args[0])))
+        })
+        .run()
+        .await
+        .unwrap();
+    server.expect_command("reboot");
+
+    let output = exec(&server, USER_LOGIN, USER_PASS, "deploy app").await;
+    assert_eq!(output, ("app\n".to_string(), 0));
+    exec(&server, USER_LOGIN, USER_PASS, "deploy db").await;
+    exec(&server, USER_LOGIN, USER_PASS, "reboot").await;
+
+    server.verify().unwrap();
+}
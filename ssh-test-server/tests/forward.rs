@@ -0,0 +1,280 @@
+use async_trait::async_trait;
+use russh::client::{self, Handle};
+use russh::Channel;
+use russh_keys::key::PublicKey;
+use ssh_test_server::{LocalForward, RemoteForward, SshServer, SshServerBuilder, User};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+async fn connect(server: &SshServer) -> Handle<common::TestClient> {
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    client
+}
+
+#[tokio::test]
+async fn test_virtual_endpoint() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_tcp_endpoint("db.internal", 5432, |mut stream| async move {
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+            stream.write_all(b"pong").await.unwrap();
+        })
+        .run()
+        .await
+        .unwrap();
+    let client = connect(&server).await;
+
+    let channel = client
+        .channel_open_direct_tcpip("db.internal", 5432, "127.0.0.1", 40000)
+        .await
+        .unwrap();
+    let mut stream = channel.into_stream();
+    stream.write_all(b"ping").await.unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(response, b"pong");
+
+    assert_eq!(
+        server.local_forwards(),
+        vec![LocalForward {
+            connection_id: 0,
+            user: USER_LOGIN.to_string(),
+            host: "db.internal".to_string(),
+            port: 5432,
+            originator_address: "127.0.0.1".to_string(),
+            originator_port: 40000,
+            accepted: true,
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_virtual_endpoint_ipv6() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_tcp_endpoint("::1", 5432, |mut stream| async move {
+            stream.write_all(b"pong").await.unwrap();
+        })
+        .run()
+        .await
+        .unwrap();
+    let client = connect(&server).await;
+
+    let channel = client
+        .channel_open_direct_tcpip("::1", 5432, "127.0.0.1", 40000)
+        .await
+        .unwrap();
+    let mut response = vec![];
+    channel
+        .into_stream()
+        .read_to_end(&mut response)
+        .await
+        .unwrap();
+    assert_eq!(response, b"pong");
+
+    let result = client
+        .channel_open_direct_tcpip("::1:5432", 0, "127.0.0.1", 40000)
+        .await;
+    assert!(result.is_err());
+
+    let accepted: Vec<_> = server
+        .local_forwards()
+        .into_iter()
+        .map(|f| (f.host, f.port, f.accepted))
+        .collect();
+    assert_eq!(
+        accepted,
+        [
+            ("::1".to_string(), 5432, true),
+            ("::1:5432".to_string(), 0, false)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_unknown_target_rejected() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+    let client = connect(&server).await;
+
+    let result = client
+        .channel_open_direct_tcpip("example.com", 80, "127.0.0.1", 40000)
+        .await;
+    assert!(result.is_err());
+
+    let forwards = server.local_forwards();
+    assert_eq!(forwards.len(), 1);
+    assert_eq!(forwards[0].host, "example.com");
+    assert!(!forwards[0].accepted);
+}
+
+#[tokio::test]
+async fn test_tcp_fallback() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 5];
+        socket.read_exact(&mut buf).await.unwrap();
+        socket.write_all(&buf).await.unwrap();
+    });
+
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .tcp_forwarding_fallback(true)
+        .run()
+        .await
+        .unwrap();
+    let client = connect(&server).await;
+
+    let channel = client
+        .channel_open_direct_tcpip("127.0.0.1", port as u32, "127.0.0.1", 40000)
+        .await
+        .unwrap();
+    let mut stream = channel.into_stream();
+    stream.write_all(b"hello").await.unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(response, b"hello");
+    assert!(server.local_forwards()[0].accepted);
+}
+
+/// Client which passes forwarded-tcpip channels opened by the server to the test.
+struct ForwardingClient {
+    channels: mpsc::UnboundedSender<(Channel<client::Msg>, String, u32)>,
+}
+
+#[async_trait]
+impl client::Handler for ForwardingClient {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, _key: &PublicKey) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: Channel<client::Msg>,
+        _connected_address: &str,
+        _connected_port: u32,
+        originator_address: &str,
+        originator_port: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let _ = self
+            .channels
+            .send((channel, originator_address.to_string(), originator_port));
+        Ok(())
+    }
+}
+
+async fn connect_forwarding(
+    server: &SshServer,
+) -> (
+    Handle<ForwardingClient>,
+    mpsc::UnboundedReceiver<(Channel<client::Msg>, String, u32)>,
+) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let config = Arc::new(client::Config::default());
+    let mut client = client::connect(config, server.addr(), ForwardingClient { channels: tx })
+        .await
+        .unwrap();
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    (client, rx)
+}
+
+#[tokio::test]
+async fn test_remote_forward() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+    let (mut client, mut channels) = connect_forwarding(&server).await;
+
+    client.tcpip_forward("127.0.0.1", 8080).await.unwrap();
+    assert_eq!(
+        server.remote_forwards(),
+        vec![RemoteForward {
+            connection_id: 0,
+            user: USER_LOGIN.to_string(),
+            address: "127.0.0.1".to_string(),
+            port: 8080,
+        }]
+    );
+
+    let mut server_stream = server
+        .open_remote_forward("127.0.0.1", 8080, "10.0.0.1", 50000)
+        .await
+        .unwrap();
+    let (channel, originator_address, originator_port) = channels.recv().await.unwrap();
+    assert_eq!(originator_address, "10.0.0.1");
+    assert_eq!(originator_port, 50000);
+
+    let mut client_stream = channel.into_stream();
+    server_stream.write_all(b"GET /").await.unwrap();
+    let mut buf = [0; 5];
+    client_stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"GET /");
+
+    client_stream.write_all(b"200 OK").await.unwrap();
+    let mut buf = [0; 6];
+    server_stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"200 OK");
+}
+
+#[tokio::test]
+async fn test_remote_forward_allocated_port_and_cancel() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+    let (mut client, _channels) = connect_forwarding(&server).await;
+
+    let port1 = client.tcpip_forward("localhost", 0).await.unwrap();
+    let port2 = client.tcpip_forward("localhost", 0).await.unwrap();
+    assert_ne!(port1, 0);
+    assert_ne!(port1, port2);
+    assert_eq!(server.remote_forwards().len(), 2);
+
+    client
+        .cancel_tcpip_forward("localhost", port1)
+        .await
+        .unwrap();
+    let forwards = server.remote_forwards();
+    assert_eq!(forwards.len(), 1);
+    assert_eq!(forwards[0].port, port2);
+    assert!(server
+        .open_remote_forward("localhost", port1, "127.0.0.1", 1)
+        .await
+        .is_err());
+
+    drop(client);
+    for _ in 0..50 {
+        if server.remote_forwards().is_empty() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("remote forwards not removed after disconnect");
+}
@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+use russh::client;
+use russh_keys::key::{self, KeyPair, PublicKey};
+use russh_keys::PublicKeyBase64;
+use ssh_test_server::{SshServer, SshServerBuilder};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+const HOST_KEY_FILE: &str = "tests/fixtures/host_ed25519";
+const HOST_PUBLIC_KEY: &str = include_str!("fixtures/host_ed25519.pub");
+const HOST_RSA_KEY_FILE: &str = "tests/fixtures/host_rsa";
+const HOST_RSA_PUBLIC_KEY: &str = include_str!("fixtures/host_rsa.pub");
+const HOST_ECDSA_KEY_FILE: &str = "tests/fixtures/host_ecdsa";
+const HOST_ECDSA_PUBLIC_KEY: &str = include_str!("fixtures/host_ecdsa.pub");
+
+/// Client that records the host key presented by the server.
+struct RecordingClient {
+    server_key: Arc<Mutex<Option<PublicKey>>>,
+}
+
+#[async_trait]
+impl client::Handler for RecordingClient {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, Self::Error> {
+        *self.server_key.lock().unwrap() = Some(key.clone());
+        Ok(true)
+    }
+}
+
+/// Connect with a client that accepts only given host key algorithm.
+async fn negotiated_host_key(server: &SshServer, algorithm: key::Name) -> Option<PublicKey> {
+    let mut config = client::Config::default();
+    config.preferred.key = Cow::Owned(vec![algorithm]);
+    let server_key = Arc::new(Mutex::new(None));
+    let handler = RecordingClient {
+        server_key: server_key.clone(),
+    };
+
+    let _ = client::connect(Arc::new(config), server.addr(), handler).await;
+    let key = server_key.lock().unwrap().take();
+    key
+}
+
+async fn multi_key_server() -> SshServer {
+    SshServerBuilder::default()
+        .host_key_file(HOST_KEY_FILE)
+        .host_key_file(HOST_RSA_KEY_FILE)
+        .host_key_file(HOST_ECDSA_KEY_FILE)
+        .run()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_host_key_from_file() {
+    let server = SshServerBuilder::default()
+        .host_key_file(HOST_KEY_FILE)
+        .run()
+        .await
+        .unwrap();
+
+    assert!(HOST_PUBLIC_KEY.starts_with(&server.server_public_key()));
+}
+
+#[tokio::test]
+async fn test_host_key_file_survives_restart() {
+    let first = SshServerBuilder::default()
+        .host_key_file(HOST_KEY_FILE)
+        .run()
+        .await
+        .unwrap();
+    let first_key = first.server_public_key();
+    drop(first);
+
+    let second = SshServerBuilder::default()
+        .host_key_file(HOST_KEY_FILE)
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(second.server_public_key(), first_key);
+}
+
+#[tokio::test]
+async fn test_host_key_missing_file() {
+    let result = SshServerBuilder::default()
+        .host_key_file("tests/fixtures/does_not_exist")
+        .run()
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_host_key_pair() {
+    let key = KeyPair::generate_ed25519();
+    let expected = format!("{} {}", key.name(), key.public_key_base64());
+
+    let server = SshServerBuilder::default()
+        .host_key(key)
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(server.server_public_key(), expected);
+}
+
+#[tokio::test]
+async fn test_host_key_seed() {
+    let first = SshServerBuilder::default()
+        .host_key_seed([1; 32])
+        .run()
+        .await
+        .unwrap();
+    let second = SshServerBuilder::default()
+        .host_key_seed([1; 32])
+        .run()
+        .await
+        .unwrap();
+    let other = SshServerBuilder::default()
+        .host_key_seed([2; 32])
+        .run()
+        .await
+        .unwrap();
+
+    assert_eq!(first.server_public_key(), second.server_public_key());
+    assert_ne!(first.server_public_key(), other.server_public_key());
+}
+
+#[tokio::test]
+async fn test_multiple_host_keys_listed_in_order() {
+    let server = multi_key_server().await;
+
+    let keys = server.server_public_keys();
+    assert_eq!(keys.len(), 3);
+    assert!(HOST_PUBLIC_KEY.starts_with(&keys[0]));
+    assert!(HOST_RSA_PUBLIC_KEY.starts_with(&keys[1]));
+    assert!(HOST_ECDSA_PUBLIC_KEY.starts_with(&keys[2]));
+    assert_eq!(server.server_public_key(), keys[0]);
+}
+
+#[tokio::test]
+async fn test_duplicate_host_key_algorithm() {
+    let error = SshServerBuilder::default()
+        .host_key(KeyPair::generate_ed25519())
+        .host_key(KeyPair::generate_ed25519())
+        .run()
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Duplicate host key algorithm ssh-ed25519"
+    );
+}
+
+#[tokio::test]
+async fn test_client_insisting_on_ed25519() {
+    let server = multi_key_server().await;
+
+    let key = negotiated_host_key(&server, key::ED25519).await.unwrap();
+    assert_eq!(key.name(), "ssh-ed25519");
+    assert!(HOST_PUBLIC_KEY.contains(&key.public_key_base64()));
+}
+
+#[tokio::test]
+async fn test_client_insisting_on_ssh_rsa() {
+    let server = multi_key_server().await;
+
+    let key = negotiated_host_key(&server, key::SSH_RSA).await.unwrap();
+    assert_eq!(key.name(), "ssh-rsa");
+    assert!(HOST_RSA_PUBLIC_KEY.contains(&key.public_key_base64()));
+}
+
+#[tokio::test]
+async fn test_client_insisting_on_rsa_sha2_256() {
+    let server = multi_key_server().await;
+
+    let key = negotiated_host_key(&server, key::RSA_SHA2_256)
+        .await
+        .unwrap();
+    assert_eq!(key.name(), "rsa-sha2-256");
+    assert!(HOST_RSA_PUBLIC_KEY.contains(&key.public_key_base64()));
+}
+
+#[tokio::test]
+async fn test_client_insisting_on_ecdsa() {
+    let server = multi_key_server().await;
+
+    let key = negotiated_host_key(&server, key::ECDSA_SHA2_NISTP256)
+        .await
+        .unwrap();
+    assert_eq!(key.name(), "ecdsa-sha2-nistp256");
+    assert!(HOST_ECDSA_PUBLIC_KEY.contains(&key.public_key_base64()));
+}
+
+#[tokio::test]
+async fn test_client_with_unsupported_algorithm_fails() {
+    let server = SshServerBuilder::default()
+        .host_key_file(HOST_KEY_FILE)
+        .run()
+        .await
+        .unwrap();
+
+    assert!(negotiated_host_key(&server, key::SSH_RSA).await.is_none());
+}
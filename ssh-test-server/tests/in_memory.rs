@@ -0,0 +1,55 @@
+use russh::client;
+use ssh_test_server::{SshServer, SshServerBuilder, User};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+async fn connect(server: &SshServer) -> Result<client::Handle<common::TestClient>, russh::Error> {
+    let config = Arc::new(client::Config::default());
+    client::connect_stream(config, server.connect_in_memory(), common::TestClient).await
+}
+
+#[tokio::test]
+async fn test_in_memory_connection() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .in_memory()
+        .run()
+        .await
+        .unwrap();
+    assert!(server.endpoints().is_empty());
+
+    let mut client = connect(&server).await.unwrap();
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    let (stdout, _, status_code) = common::exec(&client, "echo abc").await;
+    assert_eq!(stdout, "abc\n");
+    assert_eq!(status_code, 0);
+
+    let sftp = common::sftp(&client).await;
+    let mut file = sftp.create("/data.txt").await.unwrap();
+    file.write_all(b"content").await.unwrap();
+    file.shutdown().await.unwrap();
+    assert_eq!(server.fs().read("/data.txt").unwrap(), b"content");
+
+    let log = server.audit_log();
+    assert_eq!(log.connections.len(), 1);
+    assert_eq!(log.connections[0].peer_addr, None);
+    assert_eq!(log.connections[0].user.as_deref(), Some(USER_LOGIN));
+}
+
+#[tokio::test]
+async fn test_in_memory_connection_to_stopped_server() {
+    let server = SshServerBuilder::default().in_memory().run().await.unwrap();
+
+    server.stop().await;
+    assert!(connect(&server).await.is_err());
+
+    server.restart().await.unwrap();
+    assert!(connect(&server).await.is_ok());
+}
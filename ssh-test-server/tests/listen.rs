@@ -0,0 +1,90 @@
+use russh::client;
+use ssh_test_server::{Endpoint, SshServerBuilder, User};
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+async fn assert_login(mut client: client::Handle<common::TestClient>) {
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    let (stdout, _, _) = common::exec(&client, "echo abc").await;
+    assert_eq!(stdout, "abc\n");
+}
+
+#[tokio::test]
+async fn test_ipv6_bind_addr() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .bind_addr("[::1]")
+        .run()
+        .await
+        .unwrap();
+
+    assert_eq!(server.host(), "::1");
+    assert_eq!(server.addr(), format!("[::1]:{}", server.port()));
+    assert_login(common::connect(&server.addr()).await).await;
+}
+
+#[tokio::test]
+async fn test_dual_stack() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .listen("[::]:0")
+        .run()
+        .await
+        .unwrap();
+    let port = server.port();
+
+    assert_eq!(server.endpoints().len(), 1);
+    assert_login(common::connect(&format!("127.0.0.1:{port}")).await).await;
+    assert_login(common::connect(&format!("[::1]:{port}")).await).await;
+}
+
+#[tokio::test]
+async fn test_multiple_endpoints() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .bind_addr("127.0.0.1")
+        .listen("[::1]:0")
+        .run()
+        .await
+        .unwrap();
+
+    let endpoints = server.endpoints();
+    assert_eq!(endpoints.len(), 2);
+    assert_eq!(endpoints[0].to_string(), server.addr());
+    for endpoint in endpoints {
+        let Endpoint::Tcp(addr) = endpoint else {
+            panic!("unexpected endpoint {endpoint}");
+        };
+        assert_login(common::connect(&addr.to_string()).await).await;
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket() {
+    let path = std::env::temp_dir().join(format!("ssh-test-server-{}.sock", std::process::id()));
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .listen(&format!("unix:{}", path.display()))
+        .run()
+        .await
+        .unwrap();
+
+    assert_eq!(server.endpoints(), vec![Endpoint::Unix(path.clone())]);
+    assert_eq!(server.port(), 0);
+    let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    let config = std::sync::Arc::new(client::Config::default());
+    let client = client::connect_stream(config, stream, common::TestClient)
+        .await
+        .unwrap();
+    assert_login(client).await;
+    assert_eq!(server.audit_log().connections[0].peer_addr, None);
+
+    server.shutdown().await;
+    assert!(!path.exists());
+}
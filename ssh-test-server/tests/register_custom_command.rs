@@ -1,4 +1,13 @@
-use ssh_test_server::{SshExecuteContext, SshExecuteResult, SshServerBuilder, User};
+use async_trait::async_trait;
+use russh::ChannelMsg;
+use ssh_test_server::{
+    CommandPattern, SshExecuteContext, SshExecuteIo, SshExecuteResult, SshProgram,
+    SshServerBuilder, SshStreamingProgram, User,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Notify;
 mod common;
 
 const USER_LOGIN: &str = "user1";
@@ -35,7 +44,7 @@ async fn test_run_change_password() {
 async fn test_run_registered_whoami_root() {
     let (stdout, stderr, status_code) = run_command("whoami", true).await;
     assert_eq!(status_code, 0);
-    assert_eq!(stdout, "root\r\n");
+    assert_eq!(stdout, "root");
     assert_eq!(stderr, "");
 }
 
@@ -47,6 +56,271 @@ async fn test_run_registered_whoami_user() {
     assert_eq!(stderr, "");
 }
 
+#[tokio::test]
+async fn test_run_async_program() {
+    let release = Arc::new(Notify::new());
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_async_program(
+            "wait_for_release",
+            WaitForRelease {
+                release: release.clone(),
+            },
+        )
+        .run()
+        .await
+        .unwrap();
+
+    let addr = server.addr();
+    let command = tokio::spawn(async move {
+        common::run_ssh_command(&addr, USER_LOGIN, USER_PASS, |channel| {
+            channel.exec("wait_for_release now").unwrap()
+        })
+        .await
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!command.is_finished());
+
+    release.notify_one();
+    let (stdout, stderr, status_code) = command.await.unwrap();
+    assert_eq!(status_code, 0);
+    assert_eq!(stdout.trim(), "released now by user1");
+    assert_eq!(stderr, "");
+}
+
+#[tokio::test]
+async fn test_stream_program_output() {
+    let release = Arc::new(Notify::new());
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_async_program(
+            "progress",
+            Progress {
+                release: release.clone(),
+            },
+        )
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    let mut channel = client.channel_open_session().await.unwrap();
+    channel.exec(true, "progress").await.unwrap();
+
+    let mut stdout = vec![];
+    while stdout != b"started\n" {
+        match channel.wait().await.unwrap() {
+            ChannelMsg::Data { data } => stdout.extend_from_slice(&data),
+            ChannelMsg::ExitStatus { .. } => panic!("program finished before release"),
+            _ => {}
+        }
+    }
+
+    release.notify_one();
+    let mut stderr = vec![];
+    let mut status_code = None;
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } => stdout.extend_from_slice(&data),
+            ChannelMsg::ExtendedData { data, ext: 1 } => stderr.extend_from_slice(&data),
+            ChannelMsg::ExitStatus { exit_status } => status_code = Some(exit_status),
+            _ => {}
+        }
+    }
+    assert_eq!(stdout, b"started\nfinished\n");
+    assert_eq!(stderr, b"warning\n");
+    assert_eq!(status_code, Some(3));
+}
+
+#[tokio::test]
+async fn test_read_program_stdin() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_async_program("wc", WordCount)
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    let mut channel = client.channel_open_session().await.unwrap();
+    channel.exec(true, "wc -w").await.unwrap();
+    channel.data(&b"one two "[..]).await.unwrap();
+    channel.data(&b"three\n"[..]).await.unwrap();
+    channel.eof().await.unwrap();
+
+    let mut stdout = vec![];
+    let mut status_code = None;
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } => stdout.extend_from_slice(&data),
+            ChannelMsg::ExitStatus { exit_status } => status_code = Some(exit_status),
+            _ => {}
+        }
+    }
+    assert_eq!(String::from_utf8(stdout).unwrap(), "3\n");
+    assert_eq!(status_code, Some(0));
+}
+
+#[tokio::test]
+async fn test_binary_output() {
+    let (stdout, stderr, status_code) = run_russh_command("binary", false).await;
+    assert_eq!(stdout, (0..=255).collect::<Vec<u8>>());
+    assert_eq!(stderr, b"\n\0");
+    assert_eq!(status_code, 0);
+}
+
+#[tokio::test]
+async fn test_output_without_trailing_newline() {
+    let (stdout, stderr, status_code) = run_russh_command("whoami", false).await;
+    assert_eq!(stdout, USER_LOGIN.as_bytes());
+    assert_eq!(stderr, b"");
+    assert_eq!(status_code, 0);
+
+    let (stdout, stderr, status_code) = run_russh_command("x_echo abc", false).await;
+    assert_eq!(stdout, b"");
+    assert_eq!(stderr, b"x_echo: command not found\n");
+    assert_eq!(status_code, 127);
+}
+
+#[tokio::test]
+async fn test_translate_newlines_in_pty() {
+    let (stdout, stderr, status_code) = run_russh_command("lines", true).await;
+    assert_eq!(stdout, b"one\r\ntwo\r\n\r\nthree");
+    assert_eq!(stderr, b"error\r\n");
+    assert_eq!(status_code, 0);
+
+    let (stdout, stderr, _) = run_russh_command("lines", false).await;
+    assert_eq!(stdout, b"one\ntwo\n\nthree");
+    assert_eq!(stderr, b"error\n");
+}
+
+#[tokio::test]
+async fn test_raw_command_line() {
+    let command = b"raw 'a  b' \xff\xfe";
+    let (stdout, _, status_code) = run_russh_command(command, false).await;
+    assert_eq!(stdout, command);
+    assert_eq!(status_code, 0);
+
+    let (stdout, _, status_code) = run_russh_command(b"line  \"x y\"  z", false).await;
+    assert_eq!(stdout, b"line  \"x y\"  z|x y,z");
+    assert_eq!(status_code, 0);
+}
+
+#[tokio::test]
+async fn test_pattern_programs() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_pattern_program(
+            CommandPattern::glob("systemctl status *"),
+            Box::new(|_, _, args| SshExecuteResult::stdout(0, format!("{} active\n", args[1]))),
+        )
+        .add_pattern_program(
+            CommandPattern::regex(r"systemctl (start|restart) nginx").unwrap(),
+            Box::new(|_, _, _| SshExecuteResult::stdout(0, "restarted\n")),
+        )
+        .add_pattern_program(
+            CommandPattern::glob("systemctl *"),
+            Box::new(|_, _, _| SshExecuteResult::stderr(1, "unknown unit\n")),
+        )
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+
+    let output = common::exec(&client, "systemctl status nginx").await;
+    assert_eq!(output, ("nginx active\n".to_string(), "".to_string(), 0));
+    let output = common::exec(&client, "systemctl restart nginx").await;
+    assert_eq!(output, ("restarted\n".to_string(), "".to_string(), 0));
+    let output = common::exec(&client, "systemctl restart apache").await;
+    assert_eq!(output, ("".to_string(), "unknown unit\n".to_string(), 1));
+    let output = common::exec(&client, "sudo systemctl restart nginx").await;
+    assert_eq!(output.2, 127);
+}
+
+#[tokio::test]
+async fn test_programs_first_match_wins() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_pattern_program(
+            CommandPattern::glob("systemctl status *"),
+            Box::new(|_, _, _| SshExecuteResult::stdout(0, "pattern\n")),
+        )
+        .add_program(
+            "systemctl",
+            Box::new(|_, _, _| SshExecuteResult::stdout(0, "name\n")),
+        )
+        .add_pattern_program(
+            CommandPattern::glob("systemctl stop *"),
+            Box::new(|_, _, _| SshExecuteResult::stdout(0, "hidden\n")),
+        )
+        .add_pattern_program(
+            CommandPattern::glob("scp *"),
+            Box::new(|_, _, _| SshExecuteResult::stderr(1, "scp disabled\n")),
+        )
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+
+    let output = common::exec(&client, "systemctl status nginx").await;
+    assert_eq!(output.0, "pattern\n");
+    let output = common::exec(&client, "systemctl stop nginx").await;
+    assert_eq!(output.0, "name\n");
+    let output = common::exec(&client, "scp -t /tmp").await;
+    assert_eq!(output, ("".to_string(), "scp disabled\n".to_string(), 1));
+}
+
+#[tokio::test]
+async fn test_fallback_program() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .fallback_program(Box::new(|context, program, _| {
+            SshExecuteResult::stderr(
+                126,
+                format!("{program}: not allowed for {}\n", context.current_user),
+            )
+        }))
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+
+    let output = common::exec(&client, "reboot now").await;
+    assert_eq!(
+        output,
+        (
+            "".to_string(),
+            "reboot: not allowed for user1\n".to_string(),
+            126
+        )
+    );
+    let output = common::exec(&client, "echo abc").await;
+    assert_eq!(output, ("abc\n".to_string(), "".to_string(), 0));
+}
+
 async fn run_command(command: &str, root: bool) -> (String, String, i32) {
     let server = SshServerBuilder::default()
         .add_user(User::new_admin(ROOT_LOGIN, ROOT_PASS))
@@ -69,6 +343,121 @@ async fn run_command(command: &str, root: bool) -> (String, String, i32) {
     .await
 }
 
+async fn run_russh_command(command: impl Into<Vec<u8>>, pty: bool) -> (Vec<u8>, Vec<u8>, u32) {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_program("whoami", Box::new(cmd_whoami))
+        .add_program("binary", Box::new(cmd_binary))
+        .add_program("lines", Box::new(cmd_lines))
+        .add_program("line", Box::new(cmd_line))
+        .add_raw_program(
+            "raw",
+            Box::new(|_, command| SshExecuteResult::stdout(0, command)),
+        )
+        .run()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    let channel = client.channel_open_session().await.unwrap();
+    if pty {
+        channel
+            .request_pty(true, "xterm", 80, 24, 0, 0, &[])
+            .await
+            .unwrap();
+    }
+    channel.exec(true, command).await.unwrap();
+    common::output(channel).await
+}
+
+fn cmd_binary(_context: &SshExecuteContext, _program: &str, _args: &[&str]) -> SshExecuteResult {
+    SshExecuteResult {
+        stdout: (0..=255).collect(),
+        stderr: b"\n\0".to_vec(),
+        status_code: 0,
+    }
+}
+
+fn cmd_lines(_context: &SshExecuteContext, _program: &str, _args: &[&str]) -> SshExecuteResult {
+    SshExecuteResult {
+        stdout: b"one\ntwo\n\nthree".to_vec(),
+        stderr: b"error\n".to_vec(),
+        status_code: 0,
+    }
+}
+
+fn cmd_line(context: &SshExecuteContext, _program: &str, args: &[&str]) -> SshExecuteResult {
+    SshExecuteResult::stdout(0, format!("{}|{}", context.command_line, args.join(",")))
+}
+
 fn cmd_whoami(context: &SshExecuteContext, _program: &str, _args: &[&str]) -> SshExecuteResult {
     SshExecuteResult::stdout(0, context.current_user)
 }
+
+struct WaitForRelease {
+    release: Arc<Notify>,
+}
+
+#[async_trait]
+impl SshProgram for WaitForRelease {
+    async fn run(
+        &self,
+        context: &SshExecuteContext<'_>,
+        _program: &str,
+        args: &[&str],
+    ) -> SshExecuteResult {
+        self.release.notified().await;
+        SshExecuteResult::stdout(
+            0,
+            format!("released {} by {}", args.join(" "), context.current_user),
+        )
+    }
+}
+
+struct Progress {
+    release: Arc<Notify>,
+}
+
+#[async_trait]
+impl SshStreamingProgram for Progress {
+    async fn run_with_io(
+        &self,
+        _context: &SshExecuteContext<'_>,
+        _program: &str,
+        _args: &[&str],
+        io: &mut SshExecuteIo<'_>,
+    ) -> u32 {
+        io.stdout().write_all(b"started\n").await.unwrap();
+        io.stdout().flush().await.unwrap();
+        self.release.notified().await;
+        io.stderr().write_all(b"warning\n").await.unwrap();
+        io.stdout().write_all(b"finished\n").await.unwrap();
+        3
+    }
+}
+
+struct WordCount;
+
+#[async_trait]
+impl SshStreamingProgram for WordCount {
+    async fn run_with_io(
+        &self,
+        _context: &SshExecuteContext<'_>,
+        _program: &str,
+        _args: &[&str],
+        io: &mut SshExecuteIo<'_>,
+    ) -> u32 {
+        let mut input = String::new();
+        io.stdin().read_to_string(&mut input).await.unwrap();
+        let count = input.split_whitespace().count();
+        io.stdout()
+            .write_all(format!("{count}\n").as_bytes())
+            .await
+            .unwrap();
+        0
+    }
+}
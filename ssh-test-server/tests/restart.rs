@@ -0,0 +1,100 @@
+use russh::client;
+use ssh_test_server::{ServerEvent, SshServerBuilder, SshServerError, User};
+use std::sync::Arc;
+use std::time::Duration;
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+#[tokio::test]
+async fn test_stop_and_restart() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+    let addr = server.addr();
+    let mut client = common::connect(&addr).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+
+    server.stop().await;
+    assert!(client.channel_open_session().await.is_err());
+    let config = Arc::new(client::Config::default());
+    assert!(client::connect(config, addr.as_str(), common::TestClient)
+        .await
+        .is_err());
+    server
+        .users()
+        .lock()
+        .unwrap()
+        .get_mut(USER_LOGIN)
+        .unwrap()
+        .set_password("new_pass");
+
+    server.restart().await.unwrap();
+    assert_eq!(server.addr(), addr);
+    let mut client = common::connect(&addr).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, "new_pass")
+        .await
+        .unwrap());
+    let (stdout, _, _) = common::exec(&client, "echo abc").await;
+    assert_eq!(stdout, "abc\n");
+
+    let log = server.audit_log();
+    let ids: Vec<_> = log.connections.iter().map(|c| c.connection_id).collect();
+    assert_eq!(ids, vec![0, 1]);
+
+    server
+        .wait_for(|e| *e == ServerEvent::Stopped, Duration::from_secs(1))
+        .await
+        .unwrap();
+    let event = server
+        .wait_for(
+            |e| matches!(e, ServerEvent::Restarted { .. }),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(event, ServerEvent::Restarted { downtime } if downtime > Duration::ZERO));
+}
+
+#[tokio::test]
+async fn test_restart_after_shutdown() {
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap();
+    let public_key = server.server_public_key();
+
+    server.shutdown().await;
+    assert!(tokio::net::TcpStream::connect(server.addr()).await.is_err());
+
+    server.restart().await.unwrap();
+    assert_eq!(server.server_public_key(), public_key);
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn test_restart_on_taken_port() {
+    let server = SshServerBuilder::default().run().await.unwrap();
+    assert_ne!(server.port(), 0);
+
+    server.shutdown().await;
+    let _taken = tokio::net::TcpListener::bind(server.addr()).await.unwrap();
+
+    let error = server.restart().await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(SshServerError::AddrInUse { addr, .. }) if *addr == server.addr()
+    ));
+}
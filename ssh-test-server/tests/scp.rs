@@ -0,0 +1,290 @@
+use russh::client::Handle;
+use russh::ChannelMsg;
+use ssh2::Session;
+use ssh_test_server::{FileType, MemoryFs, ServerEvent, SshServer, SshServerBuilder, User};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+async fn run_server(fs: MemoryFs) -> SshServer {
+    SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .fs(fs)
+        .run()
+        .await
+        .unwrap()
+}
+
+fn ssh2_session(addr: &str) -> Session {
+    let tcp = std::net::TcpStream::connect(addr).unwrap();
+    let mut sess = Session::new().unwrap();
+    sess.set_tcp_stream(tcp);
+    sess.handshake().unwrap();
+    sess.set_timeout(5000);
+    sess.userauth_password(USER_LOGIN, USER_PASS).unwrap();
+    sess
+}
+
+async fn connect(server: &SshServer) -> Handle<common::TestClient> {
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    client
+}
+
+/// Run command with stdin, returns stdout and exit status.
+async fn exec_with_stdin(
+    client: &Handle<common::TestClient>,
+    command: &str,
+    stdin: &[u8],
+) -> (Vec<u8>, u32) {
+    let mut channel = client.channel_open_session().await.unwrap();
+    channel.exec(true, command).await.unwrap();
+    channel.data(stdin).await.unwrap();
+    channel.eof().await.unwrap();
+
+    let mut stdout = vec![];
+    let mut status_code = None;
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } => stdout.extend_from_slice(&data),
+            ChannelMsg::ExitStatus { exit_status } => status_code = Some(exit_status),
+            _ => {}
+        }
+    }
+    (stdout, status_code.unwrap())
+}
+
+#[tokio::test]
+async fn test_scp_upload() {
+    let server = run_server(MemoryFs::new()).await;
+    server.fs().create_dir_all("/upload").unwrap();
+
+    let addr = server.addr();
+    tokio::task::spawn_blocking(move || {
+        let sess = ssh2_session(&addr);
+        let content = b"scp content";
+        let mut channel = sess
+            .scp_send(
+                Path::new("/upload/file.txt"),
+                0o600,
+                content.len() as u64,
+                Some((1700000000, 1700000000)),
+            )
+            .unwrap();
+        channel.write_all(content).unwrap();
+        channel.send_eof().unwrap();
+        channel.wait_eof().unwrap();
+        channel.close().unwrap();
+        channel.wait_close().unwrap();
+    })
+    .await
+    .unwrap();
+
+    let metadata = server.fs().metadata("/upload/file.txt").unwrap();
+    assert_eq!(metadata.permissions, 0o600);
+    assert_eq!(metadata.modified, 1700000000);
+    assert_eq!(
+        server.fs().read_to_string("/upload/file.txt").unwrap(),
+        "scp content"
+    );
+}
+
+#[tokio::test]
+async fn test_scp_download() {
+    let fs = MemoryFs::new();
+    fs.write("/data/report.csv", "a,b\n1,2\n").unwrap();
+    fs.set_permissions("/data/report.csv", 0o640).unwrap();
+    let server = run_server(fs).await;
+
+    let addr = server.addr();
+    let (content, mode, size) = tokio::task::spawn_blocking(move || {
+        let sess = ssh2_session(&addr);
+        let (mut channel, stat) = sess.scp_recv(Path::new("/data/report.csv")).unwrap();
+        let mut content = vec![];
+        channel.read_to_end(&mut content).unwrap();
+        channel.send_eof().unwrap();
+        channel.wait_eof().unwrap();
+        channel.close().unwrap();
+        channel.wait_close().unwrap();
+        (content, stat.mode() & 0o777, stat.size())
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(content, b"a,b\n1,2\n");
+    assert_eq!(mode, 0o640);
+    assert_eq!(size, 8);
+}
+
+#[tokio::test]
+async fn test_scp_recursive_upload() {
+    let server = run_server(MemoryFs::new()).await;
+    server.fs().create_dir_all("/backup").unwrap();
+    let client = connect(&server).await;
+
+    let records = b"D0755 0 project\n\
+        T1600000000 0 1600000000 0\n\
+        C0644 5 README\nhello\0\
+        D0700 0 src\n\
+        C0755 4 main.sh\necho\0\
+        E\n\
+        E\n";
+    let (stdout, status_code) = exec_with_stdin(&client, "scp -r -p -t /backup", records).await;
+
+    assert_eq!(status_code, 0);
+    assert_eq!(stdout, vec![0; 10]);
+    let fs = server.fs();
+    assert_eq!(
+        fs.read_dir("/backup/project").unwrap(),
+        vec!["README", "src"]
+    );
+    assert_eq!(
+        fs.read_to_string("/backup/project/README").unwrap(),
+        "hello"
+    );
+    assert_eq!(
+        fs.metadata("/backup/project/README").unwrap().modified,
+        1600000000
+    );
+    assert_eq!(
+        fs.read_to_string("/backup/project/src/main.sh").unwrap(),
+        "echo"
+    );
+    assert_eq!(
+        fs.metadata("/backup/project/src/main.sh")
+            .unwrap()
+            .permissions,
+        0o755
+    );
+    let metadata = fs.metadata("/backup/project/src").unwrap();
+    assert_eq!(metadata.file_type, FileType::Dir);
+    assert_eq!(metadata.permissions, 0o700);
+}
+
+#[tokio::test]
+async fn test_scp_recursive_download() {
+    let fs = MemoryFs::new();
+    fs.write("/www/index.html", "<html>").unwrap();
+    fs.write("/www/css/site.css", "body{}").unwrap();
+    fs.set_permissions("/www/css", 0o750).unwrap();
+    let server = run_server(fs).await;
+    let client = connect(&server).await;
+
+    let (stdout, status_code) = exec_with_stdin(&client, "scp -r -f /www", &[0; 16]).await;
+
+    assert_eq!(status_code, 0);
+    assert_eq!(
+        String::from_utf8(stdout).unwrap(),
+        "D0755 0 www\n\
+        D0750 0 css\n\
+        C0644 6 site.css\nbody{}\0\
+        E\n\
+        C0644 6 index.html\n<html>\0\
+        E\n"
+    );
+}
+
+#[tokio::test]
+async fn test_scp_download_missing_file() {
+    let server = run_server(MemoryFs::new()).await;
+    let client = connect(&server).await;
+
+    let (stdout, status_code) = exec_with_stdin(&client, "scp -f /missing.txt", &[0]).await;
+
+    assert_eq!(status_code, 1);
+    let stdout = String::from_utf8(stdout).unwrap();
+    assert!(stdout.starts_with("\x01scp: /missing.txt: "), "{stdout:?}");
+}
+
+#[tokio::test]
+async fn test_scp_upload_without_permission() {
+    let server = run_server(MemoryFs::new()).await;
+    let fs = server.fs();
+    fs.create_dir("/ro").unwrap();
+    fs.set_permissions("/ro", 0o555).unwrap();
+    fs.write("/locked.txt", "old").unwrap();
+    fs.set_permissions("/locked.txt", 0o444).unwrap();
+    let client = connect(&server).await;
+
+    let records = b"C0644 1 a\na\0D0755 0 sub\n";
+    let (stdout, status_code) = exec_with_stdin(&client, "scp -r -t /ro", records).await;
+    assert_eq!(status_code, 1);
+    assert_eq!(
+        String::from_utf8(stdout).unwrap(),
+        "\0\0\x01scp: /ro/a: Permission denied\n\x01scp: /ro/sub: Permission denied\n"
+    );
+    assert!(fs.read_dir("/ro").unwrap().is_empty());
+
+    let records = b"C0644 3 locked.txt\nnew\0";
+    let (_, status_code) = exec_with_stdin(&client, "scp -t /locked.txt", records).await;
+    assert_eq!(status_code, 1);
+    assert_eq!(fs.read_to_string("/locked.txt").unwrap(), "old");
+}
+
+#[tokio::test]
+async fn test_scp_recursive_download_skips_unreadable() {
+    let fs = MemoryFs::new();
+    fs.write("/www/index.html", "<html>").unwrap();
+    fs.write("/www/private.txt", "secret").unwrap();
+    fs.set_permissions("/www/private.txt", 0o200).unwrap();
+    fs.write("/www/secret/key", "key").unwrap();
+    fs.set_permissions("/www/secret", 0o300).unwrap();
+    let server = run_server(fs).await;
+    let client = connect(&server).await;
+
+    let (stdout, status_code) = exec_with_stdin(&client, "scp -r -f /www", &[0; 16]).await;
+
+    assert_eq!(status_code, 1);
+    assert_eq!(
+        String::from_utf8(stdout).unwrap(),
+        "D0755 0 www\n\
+        C0644 6 index.html\n<html>\0\
+        \x01scp: /www/private.txt: Permission denied\n\
+        \x01scp: /www/secret: Permission denied\n\
+        E\n"
+    );
+}
+
+#[tokio::test]
+async fn test_scp_is_recorded() {
+    let server = run_server(MemoryFs::new()).await;
+    server.expect_command("scp -t /*").times(1);
+    let client = connect(&server).await;
+
+    let (_, status_code) = exec_with_stdin(&client, "scp -t /a.txt", b"C0644 1 a.txt\na\0").await;
+    assert_eq!(status_code, 0);
+    server
+        .wait_for(
+            |e| matches!(e, ServerEvent::CommandFinished { exit_code: 0, .. }),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+    let commands = server.audit_log().commands;
+    assert_eq!(commands.len(), 1);
+    assert_eq!(commands[0].command, "scp -t /a.txt");
+    assert_eq!(commands[0].exit_code, Some(0));
+    assert_eq!(server.fs().read_to_string("/a.txt").unwrap(), "a");
+    server.verify().unwrap();
+}
+
+#[tokio::test]
+async fn test_scp_huge_file_header() {
+    let server = run_server(MemoryFs::new()).await;
+    let client = connect(&server).await;
+
+    let records = b"C0644 1099511627776 huge.bin\nonly few bytes";
+    let (stdout, status_code) = exec_with_stdin(&client, "scp -t /huge.bin", records).await;
+
+    assert_eq!(status_code, 1);
+    assert_eq!(stdout, vec![0; 2]);
+    assert!(server.fs().read("/huge.bin").unwrap().is_empty());
+}
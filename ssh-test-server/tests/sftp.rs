@@ -0,0 +1,214 @@
+use russh::client::Handle;
+use russh_sftp::client::error::Error;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, StatusCode};
+use ssh_test_server::{FileType, MemoryFs, SshServer, SshServerBuilder, User};
+use std::io::SeekFrom;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+async fn run_server(fs: MemoryFs) -> SshServer {
+    SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .fs(fs)
+        .run()
+        .await
+        .unwrap()
+}
+
+async fn connect(server: &SshServer) -> (Handle<common::TestClient>, SftpSession) {
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    let sftp = common::sftp(&client).await;
+    (client, sftp)
+}
+
+#[tokio::test]
+async fn test_download_prepopulated_file() {
+    let fs = MemoryFs::new();
+    fs.write("/etc/motd", "Welcome!").unwrap();
+    fs.write("/etc/hostname", "test-server").unwrap();
+    let server = run_server(fs).await;
+
+    let (_client, sftp) = connect(&server).await;
+    assert_eq!(sftp.canonicalize(".").await.unwrap(), "/");
+    assert_eq!(sftp.read("/etc/motd").await.unwrap(), b"Welcome!");
+
+    let mut names: Vec<_> = sftp
+        .read_dir("/etc")
+        .await
+        .unwrap()
+        .map(|e| e.file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["hostname", "motd"]);
+}
+
+#[tokio::test]
+async fn test_upload_file() {
+    let server = run_server(MemoryFs::new()).await;
+    server.fs().create_dir_all("/upload").unwrap();
+
+    let (_client, sftp) = connect(&server).await;
+    let mut file = sftp.create("/upload/report.txt").await.unwrap();
+    file.write_all(b"line 1\nline 2\n").await.unwrap();
+    file.shutdown().await.unwrap();
+
+    assert_eq!(
+        server.fs().read_to_string("/upload/report.txt").unwrap(),
+        "line 1\nline 2\n"
+    );
+    assert_eq!(server.fs().read_dir("/upload").unwrap(), vec!["report.txt"]);
+}
+
+#[tokio::test]
+async fn test_stat_and_permissions() {
+    let fs = MemoryFs::new();
+    fs.write("/data/secret.txt", "top secret").unwrap();
+    let server = run_server(fs).await;
+
+    let (_client, sftp) = connect(&server).await;
+    let metadata = sftp.metadata("/data/secret.txt").await.unwrap();
+    assert_eq!(metadata.len(), 10);
+    assert!(metadata.is_regular());
+    assert!(sftp.metadata("/data").await.unwrap().is_dir());
+
+    let mut attrs = FileAttributes::empty();
+    attrs.permissions = Some(0o400);
+    sftp.set_metadata("/data/secret.txt", attrs).await.unwrap();
+    assert_eq!(
+        server
+            .fs()
+            .metadata("/data/secret.txt")
+            .unwrap()
+            .permissions,
+        0o400
+    );
+
+    let err = sftp
+        .write("/data/secret.txt", b"changed")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Status(s) if s.status_code == StatusCode::PermissionDenied));
+    assert_eq!(
+        server.fs().read_to_string("/data/secret.txt").unwrap(),
+        "top secret"
+    );
+
+    let err = sftp.metadata("/data/missing.txt").await.unwrap_err();
+    assert!(matches!(err, Error::Status(s) if s.status_code == StatusCode::NoSuchFile));
+}
+
+#[tokio::test]
+async fn test_rename_and_remove() {
+    let fs = MemoryFs::new();
+    fs.write("/tmp/a.txt", "a").unwrap();
+    fs.write("/tmp/dir/b.txt", "b").unwrap();
+    let server = run_server(fs).await;
+
+    let (_client, sftp) = connect(&server).await;
+    sftp.rename("/tmp/a.txt", "/tmp/c.txt").await.unwrap();
+    sftp.rename("/tmp/dir", "/tmp/moved").await.unwrap();
+    assert_eq!(
+        server.fs().read_dir("/tmp").unwrap(),
+        vec!["c.txt", "moved"]
+    );
+    assert_eq!(server.fs().read_to_string("/tmp/moved/b.txt").unwrap(), "b");
+
+    server.fs().write("/tmp/d.txt", "d").unwrap();
+    let err = sftp.rename("/tmp/c.txt", "/tmp/d.txt").await.unwrap_err();
+    assert!(matches!(err, Error::Status(s) if s.status_code == StatusCode::Failure));
+    server.fs().remove_file("/tmp/d.txt").unwrap();
+
+    assert!(sftp.remove_dir("/tmp/moved").await.is_err());
+    sftp.remove_file("/tmp/moved/b.txt").await.unwrap();
+    sftp.remove_dir("/tmp/moved").await.unwrap();
+    sftp.remove_file("/tmp/c.txt").await.unwrap();
+    sftp.create_dir("/tmp/new").await.unwrap();
+
+    assert_eq!(server.fs().read_dir("/tmp").unwrap(), vec!["new"]);
+    assert_eq!(
+        server.fs().metadata("/tmp/new").unwrap().file_type,
+        FileType::Dir
+    );
+}
+
+#[tokio::test]
+async fn test_symlinks() {
+    let fs = MemoryFs::new();
+    fs.write("/opt/app-1.0/version", "1.0").unwrap();
+    fs.symlink("/opt/app-1.0", "/opt/current").unwrap();
+    let server = run_server(fs).await;
+
+    let (_client, sftp) = connect(&server).await;
+    assert_eq!(
+        sftp.read_link("/opt/current").await.unwrap(),
+        "/opt/app-1.0"
+    );
+    assert_eq!(sftp.read("/opt/current/version").await.unwrap(), b"1.0");
+    assert!(sftp
+        .symlink_metadata("/opt/current")
+        .await
+        .unwrap()
+        .is_symlink());
+
+    sftp.symlink("version", "/opt/app-1.0/link").await.unwrap();
+    assert_eq!(
+        server.fs().read_link("/opt/app-1.0/link").unwrap(),
+        "version"
+    );
+    assert_eq!(
+        server.fs().read_to_string("/opt/current/link").unwrap(),
+        "1.0"
+    );
+}
+
+#[tokio::test]
+async fn test_file_size_limit() {
+    let fs = MemoryFs::new();
+    fs.write("/data.bin", "data").unwrap();
+    let server = run_server(fs).await;
+
+    let (_client, sftp) = connect(&server).await;
+    for size in [u64::MAX, 1 << 40] {
+        let attrs = FileAttributes {
+            size: Some(size),
+            ..FileAttributes::empty()
+        };
+        let err = sftp.set_metadata("/data.bin", attrs).await.unwrap_err();
+        assert!(matches!(err, Error::Status(s) if s.status_code == StatusCode::Failure));
+    }
+
+    for offset in [i64::MAX as u64, 1 << 40] {
+        let mut file = sftp
+            .open_with_flags("/data.bin", OpenFlags::WRITE)
+            .await
+            .unwrap();
+        file.seek(SeekFrom::Start(offset)).await.unwrap();
+        assert!(file.write_all(b"abc").await.is_err());
+    }
+    assert_eq!(server.fs().read("/data.bin").unwrap(), b"data");
+}
+
+#[tokio::test]
+async fn test_large_file_transfer() {
+    let server = run_server(MemoryFs::new()).await;
+    let content: Vec<u8> = (0..5 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+    let (_client, sftp) = connect(&server).await;
+    let mut file = sftp.create("/large.bin").await.unwrap();
+    file.write_all(&content).await.unwrap();
+    file.shutdown().await.unwrap();
+    assert_eq!(server.fs().read("/large.bin").unwrap(), content);
+
+    let mut file = sftp.open("/large.bin").await.unwrap();
+    let mut downloaded = vec![];
+    file.read_to_end(&mut downloaded).await.unwrap();
+    assert_eq!(downloaded, content);
+}
@@ -0,0 +1,155 @@
+use async_trait::async_trait;
+use russh::client::Handle;
+use ssh_test_server::{
+    ServerEvent, SshExecuteContext, SshExecuteIo, SshServer, SshServerBuilder, SshStreamingProgram,
+    User,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+mod common;
+
+const USER_LOGIN: &str = "user1";
+const USER_PASS: &str = "pass123";
+
+async fn run_server() -> SshServer {
+    SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .run()
+        .await
+        .unwrap()
+}
+
+async fn connect(server: &SshServer) -> Handle<common::TestClient> {
+    let mut client = common::connect(&server.addr()).await;
+    assert!(client
+        .authenticate_password(USER_LOGIN, USER_PASS)
+        .await
+        .unwrap());
+    client
+}
+
+async fn wait_closed(client: &Handle<common::TestClient>) {
+    for _ in 0..100 {
+        if client.is_closed() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("client is still connected");
+}
+
+#[tokio::test]
+async fn test_shutdown_disconnects_clients() {
+    let server = run_server().await;
+    let first = connect(&server).await;
+    let second = connect(&server).await;
+    common::exec(&first, "echo abc").await;
+
+    tokio::time::timeout(Duration::from_secs(5), server.shutdown())
+        .await
+        .unwrap();
+
+    let log = server.audit_log();
+    assert_eq!(log.connections.len(), 2);
+    assert!(log.connections.iter().all(|c| c.disconnected_at.is_some()));
+    wait_closed(&first).await;
+    wait_closed(&second).await;
+    assert!(first.channel_open_session().await.is_err());
+    assert!(tokio::net::TcpStream::connect(server.addr()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_drop_disconnects_clients() {
+    let server = run_server().await;
+    let client = connect(&server).await;
+    server
+        .wait_for(
+            |e| matches!(e, ServerEvent::AuthSucceeded { .. }),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+    drop(server);
+    wait_closed(&client).await;
+    assert!(client.channel_open_session().await.is_err());
+}
+
+/// Program which never finishes, sets the flag when it's dropped.
+struct Hang(Arc<AtomicBool>);
+
+struct SetOnDrop(Arc<AtomicBool>);
+
+impl Drop for SetOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl SshStreamingProgram for Hang {
+    async fn run_with_io(
+        &self,
+        _context: &SshExecuteContext<'_>,
+        _program: &str,
+        _args: &[&str],
+        _io: &mut SshExecuteIo<'_>,
+    ) -> u32 {
+        let _guard = SetOnDrop(self.0.clone());
+        std::future::pending().await
+    }
+}
+
+#[tokio::test]
+async fn test_shutdown_aborts_running_commands() {
+    let dropped = Arc::new(AtomicBool::new(false));
+    let server = SshServerBuilder::default()
+        .add_user(User::new(USER_LOGIN, USER_PASS))
+        .add_async_program("hang", Hang(dropped.clone()))
+        .run()
+        .await
+        .unwrap();
+    let client = connect(&server).await;
+    let channel = client.channel_open_session().await.unwrap();
+    channel.exec(true, "hang").await.unwrap();
+    server
+        .wait_for(
+            |e| matches!(e, ServerEvent::CommandStarted { .. }),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), server.shutdown())
+        .await
+        .unwrap();
+    for _ in 0..100 {
+        if dropped.load(Ordering::SeqCst) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("command is still running");
+}
+
+#[tokio::test]
+async fn test_shutdown_aborts_clients_ignoring_disconnect() {
+    let server = run_server().await;
+    let mut stream = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+    // Client sends its identification and ignores everything the server sends.
+    stream.write_all(b"SSH-2.0-ignoring\r\n").await.unwrap();
+    let mut server_id = [0; 8];
+    stream.read_exact(&mut server_id).await.unwrap();
+    assert_eq!(&server_id, b"SSH-2.0-");
+
+    tokio::time::timeout(Duration::from_secs(10), server.shutdown())
+        .await
+        .unwrap();
+    let mut received = vec![];
+    tokio::time::timeout(Duration::from_secs(1), stream.read_to_end(&mut received))
+        .await
+        .expect("session is still running")
+        .unwrap();
+}